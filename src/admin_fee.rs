@@ -0,0 +1,25 @@
+use near_sdk::AccountId;
+
+/// Fees of the exchange and the referral taken out of a swap's total fee.
+/// Both are given in basis points of the pool's total fee (divided by FEE_DIVISOR).
+pub struct AdminFees {
+    /// Basis points of the fee for exchange.
+    pub exchange_fee: u32,
+    /// Basis points of the fee for referrer.
+    pub referral_fee: u32,
+    /// Account receiving the exchange share.
+    pub exchange_id: AccountId,
+    /// Account receiving the referral share, if any was passed with the swap.
+    pub referral_id: Option<AccountId>,
+}
+
+impl AdminFees {
+    pub fn new(exchange_fee: u32, referral_fee: u32, exchange_id: AccountId, referral_id: Option<AccountId>) -> Self {
+        AdminFees {
+            exchange_fee,
+            referral_fee,
+            exchange_id,
+            referral_id,
+        }
+    }
+}
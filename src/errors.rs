@@ -0,0 +1,107 @@
+//! Error codes of all failure paths of the contract.
+//! Every message starts with a stable "E##" code, clients should match on the code only.
+
+macro_rules! errors {
+    ($($name:ident = $message:expr;)*) => {
+        $(pub const $name: &str = $message;)*
+
+        /// All error messages, ordered by code.
+        pub const ERRORS: &[&str] = &[$($name),*];
+    };
+}
+
+errors! {
+    // Accounts and storage.
+    ERR10_ACC_NOT_REGISTERED = "E10: account not registered";
+    ERR11_INSUFFICIENT_STORAGE = "E11: insufficient $NEAR storage deposit";
+    ERR12_STORAGE_DEPOSIT_LESS_THAN_MIN = "E12: deposit less than min storage";
+    ERR13_LP_NOT_REGISTERED = "E13: LP not registered";
+    ERR14_LP_ALREADY_REGISTERED = "E14: LP already registered";
+    ERR15_NO_STORAGE_CAN_WITHDRAW = "E15: no storage can withdraw";
+    ERR16_STORAGE_WITHDRAW_TOO_MUCH = "E16: storage withdraw too much";
+    ERR17_DEPOSIT_LESS_THAN_STORAGE_COST = "E17: attached deposit less than storage cost";
+    ERR18_TOKENS_NOT_EMPTY = "E18: storage unregister with non-empty token deposits";
+    ERR19_ATTACHED_DEPOSIT_REQUIRED = "E19: requires attached deposit of at least 1 yoctoNEAR";
+
+    // Token deposits.
+    ERR20_TOKEN_NOT_WHITELISTED = "E20: token not whitelisted";
+    ERR21_TOKEN_NOT_REG = "E21: token not registered";
+    ERR22_NOT_ENOUGH_TOKENS = "E22: not enough tokens in deposit";
+    ERR23_TOKEN_DUPLICATES = "E23: duplicated tokens";
+    ERR24_NON_ZERO_TOKEN_BALANCE = "E24: non-zero token balance";
+    ERR25_CALLBACK_POST_WITHDRAW_INVALID = "E25: expected 1 promise result from withdraw";
+    ERR26_ZERO_DEPOSIT = "E26: deposit of zero amount";
+    ERR27_SHARES_NOT_EMPTY = "E27: storage unregister with non-empty LP shares";
+    ERR28_WRONG_MSG_FORMAT = "E28: illegal msg in ft_transfer_call";
+    ERR29_ILLEGAL_WITHDRAW_AMOUNT = "E29: illegal withdraw amount";
+
+    // Liquidity.
+    ERR30_WRONG_TOKEN_COUNT = "E30: wrong token count";
+    ERR31_ZERO_AMOUNT = "E31: adding zero amount";
+    ERR32_ZERO_SHARES = "E32: minting zero shares";
+    ERR33_NO_SHARES = "E33: no shares";
+    ERR34_NOT_ENOUGH_SHARES = "E34: not enough shares";
+    ERR35_MIN_AMOUNT = "E35: amount less than min amount";
+    ERR36_SLIPPAGE = "E36: shares out of slippage bounds";
+    ERR37_MIN_LIQUIDITY = "E37: liquidity below locked minimum";
+    ERR38_LP_SHARES_CALC = "E38: shares calculation failed";
+    ERR39_BURN_ZERO_SHARES = "E39: burning zero shares";
+
+    // Swaps.
+    ERR40_AT_LEAST_ONE_SWAP = "E40: at least one swap action";
+    ERR41_MISSING_TOKEN = "E41: token not in pool";
+    ERR42_NO_PREV_AMOUNT = "E42: no output of previous action to chain";
+    ERR43_WRONG_CHAINED_TOKEN = "E43: chained action takes other token than previous output";
+    ERR44_INSTANT_SWAP_NOT_CHAINED = "E44: instant swap actions must be chained";
+    ERR45_AMOUNT_IN_TOO_LARGE = "E45: amount in exceeds transferred amount";
+    ERR46_INVARIANT = "E46: pool invariant decreased";
+    ERR47_SWAP_CALC = "E47: swap calculation failed";
+    ERR48_INVALID_SWAP = "E48: invalid swap parameters";
+    ERR49_MAX_IN_RATIO = "E49: amount in exceeds max ratio of pool balance";
+
+    // Pool configuration.
+    ERR50_FEE_TOO_LARGE = "E50: fee too large";
+    ERR51_SHOULD_HAVE_2_TOKENS = "E51: simple pool should have 2 tokens";
+    ERR52_WRONG_DECIMALS_COUNT = "E52: wrong decimals count";
+    ERR53_DECIMALS_TOO_LARGE = "E53: decimals too large";
+    ERR54_INVALID_AMP = "E54: invalid amplification coefficient";
+    ERR55_RAMP_LOCKED = "E55: amplification ramp locked";
+    ERR56_INSUFFICIENT_RAMP_TIME = "E56: insufficient ramp time";
+    ERR57_AMP_CHANGE_TOO_LARGE = "E57: amplification change too large";
+    ERR58_NOT_STABLE_POOL = "E58: not a stable swap pool";
+    ERR59_AMOUNT_OVERFLOW = "E59: amount overflow";
+
+    // Weighted and rated pools.
+    ERR60_WRONG_WEIGHTS_COUNT = "E60: wrong weights count";
+    ERR61_WRONG_WEIGHTS_SUM = "E61: weights should sum to 10000";
+    ERR62_WEIGHT_TOO_SMALL = "E62: weight too small";
+    ERR63_BPOW_BASE = "E63: power base out of range";
+    ERR64_NO_RATED_TOKENS = "E64: no rated tokens";
+    ERR65_RATE_STALE = "E65: token rate is stale";
+    ERR66_ZERO_RATE = "E66: zero token rate";
+    ERR67_INVARIANT_CALC = "E67: invariant calculation failed";
+    ERR68_TOKEN_NOT_RATED = "E68: token is not rated in any pool";
+    ERR69_MAX_OUT_RATIO = "E69: amount out exceeds max ratio of pool balance";
+
+    // Multi fungible tokens.
+    ERR70_ILLEGAL_TOKEN_ID = "E70: illegal token id";
+    ERR71_TRANSFER_TO_SELF = "E71: transfer to self";
+    ERR72_ZERO_TRANSFER = "E72: transfer of zero amount";
+    ERR73_NOT_ENOUGH_GAS = "E73: not enough gas attached to transfer call";
+
+    // Force unregister.
+    ERR80_TOO_MANY_TOKENS = "E80: too many tokens to send on force unregister";
+
+    // Pools.
+    ERR85_NO_POOL = "E85: invalid pool id";
+    ERR86_POOL_FROZEN = "E86: pool is frozen";
+    ERR87_POOL_NOT_FROZEN = "E87: pool is not frozen";
+
+    // Owner and guardians.
+    ERR90_NOT_ALLOWED = "E90: not allowed";
+    ERR91_NO_PENDING_OWNER = "E91: no pending owner";
+    ERR92_GUARDIAN_NOT_IN_LIST = "E92: guardian not in list";
+    ERR93_TOKEN_NOT_FROZEN = "E93: token is not frozen";
+    ERR94_CONTRACT_PAUSED = "E94: contract paused";
+    ERR95_NOT_INITIALIZED = "E95: contract is not initialized";
+}
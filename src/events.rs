@@ -0,0 +1,186 @@
+//! NEP-297 events of all state changes, logged as "EVENT_JSON:" followed by the event json.
+
+use near_sdk::json_types::{ValidAccountId, WrappedTimestamp, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+use near_sdk::{env, AccountId};
+
+use crate::RunningState;
+
+const EVENT_STANDARD: &str = "ref-exchange";
+const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// Tokens deposited to the account through `ft_transfer_call`.
+    Deposit {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Tokens withdrawn from the account, transfer to the account is in flight.
+    Withdraw {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Withdrawal transfer failed and tokens were returned to the account.
+    WithdrawRefund {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Withdrawal transfer failed and tokens could not be returned, they were stored to the owner's account.
+    Lostfound {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    RegisterTokens {
+        account_id: &'a AccountId,
+        token_ids: &'a [ValidAccountId],
+    },
+    UnregisterTokens {
+        account_id: &'a AccountId,
+        token_ids: &'a [ValidAccountId],
+    },
+    Swap {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        token_in: &'a AccountId,
+        amount_in: U128,
+        token_out: &'a AccountId,
+        amount_out: U128,
+        referral_id: Option<&'a AccountId>,
+    },
+    AddLiquidity {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        shares: U128,
+    },
+    RemoveLiquidity {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        shares: U128,
+    },
+    CreatePool {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        pool_kind: String,
+        token_ids: &'a [AccountId],
+    },
+    /// Pool shares transferred as multi fungible token.
+    ShareTransfer {
+        pool_id: u64,
+        sender_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+        amount: U128,
+        memo: Option<&'a str>,
+    },
+    /// New rate of the token cached in given rated pools.
+    UpdateTokenRate {
+        token_id: &'a AccountId,
+        rate: U128,
+        pool_ids: &'a [u64],
+    },
+    StorageDeposit {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    StorageWithdraw {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    StorageUnregister {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    // Admin actions, `account_id` is the owner or guardian who performed them.
+    SetOwner {
+        account_id: &'a AccountId,
+        owner_id: &'a AccountId,
+    },
+    AcceptOwner {
+        account_id: &'a AccountId,
+    },
+    ExtendGuardians {
+        account_id: &'a AccountId,
+        guardians: &'a [ValidAccountId],
+    },
+    RemoveGuardians {
+        account_id: &'a AccountId,
+        guardians: &'a [ValidAccountId],
+    },
+    ChangeState {
+        account_id: &'a AccountId,
+        state: &'a RunningState,
+    },
+    FreezePools {
+        account_id: &'a AccountId,
+        pool_ids: &'a [u64],
+    },
+    UnfreezePools {
+        account_id: &'a AccountId,
+        pool_ids: &'a [u64],
+    },
+    ExtendFrozenTokens {
+        account_id: &'a AccountId,
+        token_ids: &'a [ValidAccountId],
+    },
+    RemoveFrozenTokens {
+        account_id: &'a AccountId,
+        token_ids: &'a [ValidAccountId],
+    },
+    ExtendWhitelistedTokens {
+        account_id: &'a AccountId,
+        token_ids: &'a [ValidAccountId],
+    },
+    RemoveWhitelistedTokens {
+        account_id: &'a AccountId,
+        token_ids: &'a [ValidAccountId],
+    },
+    SetWhitelistedPoolTokensOnly {
+        account_id: &'a AccountId,
+        enabled: bool,
+    },
+    RampAmp {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        future_amp_factor: u64,
+        future_amp_time: WrappedTimestamp,
+    },
+    StopRampAmp {
+        account_id: &'a AccountId,
+        pool_id: u64,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+impl Event<'_> {
+    pub fn emit(&self) {
+        let event_log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_STANDARD_VERSION,
+            event: self,
+        };
+        env::log(
+            format!(
+                "EVENT_JSON:{}",
+                serde_json::to_string(&event_log).unwrap()
+            )
+            .as_bytes(),
+        );
+    }
+}
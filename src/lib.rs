@@ -1,81 +1,173 @@
+// Lints of the code predating the lint gate, kept as it was written.
+#![allow(
+    clippy::assign_op_pattern,
+    clippy::expect_fun_call,
+    clippy::implicit_saturating_sub,
+    clippy::manual_div_ceil,
+    clippy::manual_map,
+    clippy::manual_saturating_arithmetic,
+    clippy::needless_borrow,
+    clippy::redundant_closure,
+    clippy::unnecessary_cast
+)]
+
 use std::collections::HashMap;
 
 use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
 use near_sdk::collections::{ UnorderedMap, LookupMap, UnorderedSet, Vector};
-use near_sdk::{AccountId, Balance, env, near_bindgen,
-     BorshStorageKey, StorageUsage, log, Promise, Gas, PromiseOrValue, PromiseResult
+use near_sdk::{AccountId, Balance, env, near_bindgen, assert_one_yocto,
+     BorshStorageKey, StorageUsage, log, Promise, Gas, PromiseResult
      };
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
 use near_contract_standards::storage_management::{
     StorageBalance, StorageBalanceBounds, StorageManagement,
 };
-use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
-use pool::Pool;
+use action::SwapAction;
+use admin_fee::AdminFees;
+use pool::{Pool, VPool};
 use simple_pool::SimplePool;
-use utils::ext_self;
-use crate::utils::check_token_duplicates;
+use stable_swap::StableSwapPool;
+use weighted_pool::WeightedPool;
+use rated_swap::RatedSwapPool;
+use errors::*;
+use events::Event;
+use utils::{ext_rate_source, ext_self};
+use crate::utils::{check_token_duplicates, FEE_DIVISOR};
 
 
+mod action;
+mod errors;
+mod events;
+mod admin_fee;
 mod utils;
 mod storage_impl;
 mod pool;
 mod simple_pool;
+mod stable_swap;
+mod weighted_pool;
+mod rated_swap;
 mod views;
+mod token_receiver;
+mod owner;
+mod multi_fungible_token;
+mod upgrade;
 
 pub const GAS_FOR_FT_TRANSFER: Gas = 20_000_000_000_000;
 pub const GAS_FOR_RESOLVE_TRANSFER: Gas = 20_000_000_000_000;
+pub const GAS_FOR_RATE_QUERY: Gas = 10_000_000_000_000;
+pub const GAS_FOR_RATE_CALLBACK: Gas = 10_000_000_000_000;
+pub const GAS_FOR_MFT_TRANSFER_CALL: Gas = 45_000_000_000_000;
+
+
+// Storage actually used by accounts is measured from `env::storage_usage` deltas and kept in
+// `Account::storage_used`. The sizes below are measured for the longest, 64 bytes, account ids
+// and only serve as upper bounds for the minimal registration deposit.
+
+/// Account record with empty collections, including the 40 bytes overhead of every storage record.
+/// Collections keep their key prefixes, which contain the account id, in the record.
+pub const INIT_ACCOUNT_STORAGE: StorageUsage = 528;
+
+/// Entry in the contract's registry of account ids, which is kept in 2 storage records.
+const ACCOUNT_ID_ENTRY_STORAGE: StorageUsage = 236;
 
-pub const ERR11_INSUFFICIENT_STORAGE: &str = "E11: insufficient $NEAR storage deposit";
-pub const ERR24_NON_ZERO_TOKEN_BALANCE: &str = "E24: non-zero token balance";
-pub const ERR21_TOKEN_NOT_REG: &str = "E21: token not registered";
-pub const ERR29_ILLEGAL_WITHDRAW_AMOUNT: &str = "E29: Illegal withdraw amount";
-pub const ERR22_NOT_ENOUGH_TOKENS: &str = "E22: not enough tokens in deposit";
-pub const ERR25_CALLBACK_POST_WITHDRAW_INVALID: &str =
-    "E25: expected 1 promise result from withdraw";
-
-const U128_STORAGE: StorageUsage = 16;
-const U64_STORAGE: StorageUsage = 8;
-const U32_STORAGE: StorageUsage = 4;
-/// max length of account id is 64 bytes. We charge per byte.
-const ACC_ID_STORAGE: StorageUsage = 64;
-/// As a key, 4 bytes length would be added to the head
-const ACC_ID_AS_KEY_STORAGE: StorageUsage = ACC_ID_STORAGE + 4;
-const KEY_PREFIX_ACC: StorageUsage = 64;
-/// As a near_sdk::collection key, 1 byte for prefiex
-const ACC_ID_AS_CLT_KEY_STORAGE: StorageUsage = ACC_ID_AS_KEY_STORAGE + 1;
-
-// ACC_ID: the Contract accounts map key length
-// + VAccount enum: 1 byte
-// + U128_STORAGE: near_amount storage
-// + U32_STORAGE: tokens HashMap length
-// + U64_STORAGE: storage_used
-pub const INIT_ACCOUNT_STORAGE: StorageUsage =
-    ACC_ID_AS_CLT_KEY_STORAGE + 1 + U128_STORAGE + U32_STORAGE + U64_STORAGE;
+/// Storage legacy accounts were charged for by the first deployment, without the token entries.
+const LEGACY_ACCOUNT_STORAGE: StorageUsage = 98;
+
+/// Storage legacy accounts were charged for by the first deployment, per token entry.
+const LEGACY_ACCOUNT_TOKEN_STORAGE: StorageUsage = 148;
 
 
 #[derive(BorshStorageKey, BorshSerialize)]
 pub(crate) enum StorageKey {
     Pools,
+    /// Accounts of the baseline layout, read by `legacy_accounts`.
     Accounts,
     AccountTokens {account_id: AccountId},
     Shares { pool_id: u32 },
     Whitelist,
+    AccountWhitelist { account_id: AccountId },
+    Guardian,
+    FrozenPools,
+    FrozenTokens,
+    AccountIds,
+    VersionedAccounts,
+    AccountPools,
+    RatedPools,
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Account {
     pub near_amount: Balance,
-    pub tokens : UnorderedMap<AccountId, Balance>
+    pub tokens : UnorderedMap<AccountId, Balance>,
+    /// Tokens this user accepts deposits of, on top of the global whitelist.
+    pub whitelisted_tokens: UnorderedSet<AccountId>,
+    /// Bytes of storage taken by this account, its deposits and its LP shares.
+    pub storage_used: StorageUsage,
+}
+
+/// Account layout of the first deployment, stored untagged under `StorageKey::Accounts`.
+/// These accounts are moved to the versioned accounts when they are saved next time.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountV0 {
+    pub near_amount: Balance,
+    pub tokens : UnorderedMap<AccountId, Balance>,
+}
+
+impl AccountV0 {
+    /// Converts into the current layout, keeping the stored token deposits.
+    pub fn into_current(self, account_id: &AccountId) -> Account {
+        // Storage of legacy accounts can't be measured anymore, they keep what they were charged for.
+        // Moving the record to the versioned accounts is not charged, see `internal_save_account`.
+        let mut account = Account::new(account_id);
+        account.near_amount = self.near_amount;
+        account.storage_used =
+            LEGACY_ACCOUNT_STORAGE + self.tokens.len() * LEGACY_ACCOUNT_TOKEN_STORAGE;
+        account.tokens = self.tokens;
+        account
+    }
+}
+
+/// Versioned account, stored in the contract's accounts.
+/// Accounts stored with older layouts are converted to the current one when read.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VAccount {
+    Current(Account),
+}
+
+impl From<VAccount> for Account {
+    fn from(v_account: VAccount) -> Self {
+        match v_account {
+            VAccount::Current(account) => account,
+        }
+    }
+}
+
+impl From<Account> for VAccount {
+    fn from(account: Account) -> Self {
+        VAccount::Current(account)
+    }
 }
 
 impl Default for Contract {
     fn default() -> Self {
         Self {
             owner_id: env::predecessor_account_id(),
-            accounts: LookupMap::new(StorageKey::Accounts),
+            pending_owner_id: None,
+            guardians: UnorderedSet::new(StorageKey::Guardian),
+            accounts: LookupMap::new(StorageKey::VersionedAccounts),
+            legacy_accounts: LookupMap::new(StorageKey::Accounts),
+            account_pools: LookupMap::new(StorageKey::AccountPools),
+            legacy_pool_count: 0,
+            rated_pools: LookupMap::new(StorageKey::RatedPools),
             whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
+            whitelisted_pool_tokens_only: false,
             pools: Vector::new(StorageKey::Pools),
+            state: RunningState::Running,
+            frozen_pools: UnorderedSet::new(StorageKey::FrozenPools),
+            frozen_tokens: UnorderedSet::new(StorageKey::FrozenTokens),
+            account_ids: UnorderedSet::new(StorageKey::AccountIds),
             exchange_fee: 0,
             referral_fee: 0,
         }
@@ -89,6 +181,10 @@ impl Account {
             tokens: UnorderedMap::new(StorageKey::AccountTokens {
                 account_id: account_id.clone(),
             }),
+            whitelisted_tokens: UnorderedSet::new(StorageKey::AccountWhitelist {
+                account_id: account_id.clone(),
+            }),
+            storage_used: 0,
         }
     }
 
@@ -106,21 +202,35 @@ impl Account {
             if self.get_balance(t).is_none() {
                 self.tokens.insert(t, &0);
             }
+            self.whitelisted_tokens.insert(t);
         }
     }
 
     pub fn unregister (&mut self, token_id: &AccountId) {
         let amount = self.tokens.remove(token_id).unwrap_or_default();
-        assert_eq!(amount, 0, "{}", ERR24_NON_ZERO_TOKEN_BALANCE)
+        assert_eq!(amount, 0, "{}", ERR24_NON_ZERO_TOKEN_BALANCE);
+        self.whitelisted_tokens.remove(token_id);
+    }
+
+    /// Returns true if this user accepts deposits of given token.
+    pub fn is_whitelisted(&self, token_id: &AccountId) -> bool {
+        self.whitelisted_tokens.contains(token_id)
     }
 
     // [AUDIT_01]
     /// Returns amount of $NEAR necessary to cover storage used by this data structure.
     pub fn storage_usage(&self) -> Balance {
-        (INIT_ACCOUNT_STORAGE + 
-            self.tokens.len() as u64 * (KEY_PREFIX_ACC + ACC_ID_AS_KEY_STORAGE + U128_STORAGE)
-        ) as u128
-            * env::storage_byte_cost()
+        self.storage_used as Balance * env::storage_byte_cost()
+    }
+
+    /// Charges storage taken or released since `prev_storage` to this account.
+    pub(crate) fn update_storage_used(&mut self, prev_storage: StorageUsage) {
+        let storage_usage = env::storage_usage();
+        if storage_usage >= prev_storage {
+            self.storage_used += storage_usage - prev_storage;
+        } else {
+            self.storage_used = self.storage_used.saturating_sub(prev_storage - storage_usage);
+        }
     }
 
     /// Asserts there is sufficient amount of $NEAR to cover storage usage.
@@ -134,7 +244,7 @@ impl Account {
 
     /// Returns minimal account deposit storage usage possible.
     pub fn min_storage_usage() -> Balance {
-        INIT_ACCOUNT_STORAGE as Balance * env::storage_byte_cost()
+        (INIT_ACCOUNT_STORAGE + ACCOUNT_ID_ENTRY_STORAGE) as Balance * env::storage_byte_cost()
     }
 
     // 
@@ -162,11 +272,15 @@ impl Account {
             true
         } else {
             // check storage after insert, if fail should unregister the token
+            let prev_storage = env::storage_usage();
+            let prev_storage_used = self.storage_used;
             self.tokens.insert(token, &(amount));
+            self.update_storage_used(prev_storage);
             if self.storage_usage() <= self.near_amount {
                 true
             } else {
                 self.tokens.remove(token);
+                self.storage_used = prev_storage_used;
                 false
             }
         }
@@ -193,26 +307,68 @@ impl Account {
     }
 }
 
+/// Running state of the contract, operations besides withdrawals are blocked while paused.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RunningState {
+    Running,
+    Paused,
+}
+
 #[derive(BorshDeserialize, BorshSerialize)]
 #[near_bindgen]
 pub struct Contract {
     owner_id: AccountId,
-    accounts: LookupMap<AccountId, Account>,
+    /// Account proposed as the new owner, has to accept ownership to become owner.
+    pending_owner_id: Option<AccountId>,
+    /// Accounts allowed to perform emergency actions.
+    guardians: UnorderedSet<AccountId>,
+    accounts: LookupMap<AccountId, VAccount>,
     whitelisted_tokens: UnorderedSet<AccountId>,
+    /// If set, pools can only be created from whitelisted tokens.
+    whitelisted_pool_tokens_only: bool,
     exchange_fee: u32,
     referral_fee: u32,
-    pools: Vector<Pool>,
+    pools: Vector<VPool>,
+    /// Running state of the contract.
+    state: RunningState,
+    /// Pools that reject swaps and liquidity adds.
+    frozen_pools: UnorderedSet<u64>,
+    /// Tokens that make every pool containing them reject swaps and liquidity adds.
+    frozen_tokens: UnorderedSet<AccountId>,
+    /// Ids of all registered accounts, so they can be enumerated.
+    account_ids: UnorderedSet<AccountId>,
+    /// Accounts registered before the upgrade, in their original layout.
+    legacy_accounts: LookupMap<AccountId, AccountV0>,
+    /// Ids of pools each account has a shares entry in, so they can be found without a scan over all pools.
+    account_pools: LookupMap<AccountId, Vec<u64>>,
+    /// Number of pools created before the upgrade, where shares entries of legacy accounts are not tracked.
+    legacy_pool_count: u64,
+    /// Ids of rated swap pools each rated token is in, so its rate can be updated in all of them.
+    rated_pools: LookupMap<AccountId, Vec<u64>>,
 }
 
 #[near_bindgen]
 impl Contract {
     #[init]
     pub fn new(owner_id: ValidAccountId, exchange_fee: u32, referral_fee: u32) -> Self {
+        assert!(exchange_fee + referral_fee <= FEE_DIVISOR, "{}", ERR50_FEE_TOO_LARGE);
         Self {
             owner_id: owner_id.as_ref().clone(),
-            accounts: LookupMap::new(StorageKey::Accounts),
+            pending_owner_id: None,
+            guardians: UnorderedSet::new(StorageKey::Guardian),
+            accounts: LookupMap::new(StorageKey::VersionedAccounts),
+            legacy_accounts: LookupMap::new(StorageKey::Accounts),
+            account_pools: LookupMap::new(StorageKey::AccountPools),
+            legacy_pool_count: 0,
+            rated_pools: LookupMap::new(StorageKey::RatedPools),
             whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
+            whitelisted_pool_tokens_only: false,
             pools: Vector::new(StorageKey::Pools),
+            state: RunningState::Running,
+            frozen_pools: UnorderedSet::new(StorageKey::FrozenPools),
+            frozen_tokens: UnorderedSet::new(StorageKey::FrozenTokens),
+            account_ids: UnorderedSet::new(StorageKey::AccountIds),
             exchange_fee,
             referral_fee,
     }}
@@ -229,6 +385,58 @@ impl Contract {
         )))
     }
 
+    #[payable]
+    pub fn add_stable_swap_pool(
+        &mut self,
+        tokens: Vec<ValidAccountId>,
+        decimals: Vec<u8>,
+        fee: u32,
+        amp_factor: u64,
+    ) -> u64 {
+        check_token_duplicates(&tokens);
+        self.internal_add_pool(Pool::StableSwapPool(StableSwapPool::new(
+            self.pools.len() as u32,
+            tokens,
+            decimals,
+            amp_factor as u128,
+            fee,
+        )))
+    }
+
+    /// Creates weighted pool with 2 to 8 tokens, `weights` are given in basis points and sum up to 10000.
+    #[payable]
+    pub fn add_weighted_pool(&mut self, tokens: Vec<ValidAccountId>, weights: Vec<u32>, fee: u32) -> u64 {
+        check_token_duplicates(&tokens);
+        self.internal_add_pool(Pool::WeightedPool(WeightedPool::new(
+            self.pools.len() as u32,
+            tokens,
+            weights,
+            fee,
+        )))
+    }
+
+    /// Creates stable swap pool, where balances of `rated_tokens` are valued by the rate
+    /// their contracts report via `ft_price`. Rates must be fetched with `update_token_rate` before use.
+    #[payable]
+    pub fn add_rated_swap_pool(
+        &mut self,
+        tokens: Vec<ValidAccountId>,
+        decimals: Vec<u8>,
+        rated_tokens: Vec<ValidAccountId>,
+        fee: u32,
+        amp_factor: u64,
+    ) -> u64 {
+        check_token_duplicates(&tokens);
+        self.internal_add_pool(Pool::RatedSwapPool(RatedSwapPool::new(
+            self.pools.len() as u32,
+            tokens,
+            decimals,
+            rated_tokens,
+            amp_factor as u128,
+            fee,
+        )))
+    }
+
     #[payable]
     pub fn add_liquidity(
         &mut self,
@@ -238,73 +446,161 @@ impl Contract {
     ) {
         assert!(
             env::attached_deposit() > 0,
-            "Requires attached deposit of at least 1 yoctoNEAR"
+            "{}", ERR19_ATTACHED_DEPOSIT_REQUIRED
         );
-        let prev_storage = env::storage_usage();
-        let sender_id = env::predecessor_account_id();
         let mut amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
-        pool.add_liquidity(
-            &sender_id,
-            &mut amounts
-        );
+        self.internal_add_liquidity(pool_id, &mut amounts);
         if let Some(min_amounts) = min_amounts {
             for (amount, min_amount) in amounts.iter().zip(min_amounts.iter()) {
-                assert!(amount >= &min_amount.0, "ERR_MIN_AMOUNT");
+                assert!(amount >= &min_amount.0, "{}", ERR35_MIN_AMOUNT);
             }
         }
-        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
-        let tokens = pool.tokens();
-        for i in 0..tokens.len() {
-            deposits.withdraw(&tokens[i], amounts[i]);
-        }
-        self.internal_save_account(&sender_id, deposits);
-        self.pools.replace(pool_id, &pool);
-        self.internal_check_storage(prev_storage);
+    }
+
+    /// Adds any amounts of tokens to the stable swap pool, imbalanced deposit is charged with fee.
+    /// Fails if minted shares are less than `min_shares`. Returns number of minted shares.
+    #[payable]
+    pub fn add_stable_liquidity(&mut self, pool_id: u64, amounts: Vec<U128>, min_shares: U128) -> U128 {
+        assert!(
+            env::attached_deposit() > 0,
+            "{}", ERR19_ATTACHED_DEPOSIT_REQUIRED
+        );
+        let mut amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
+        let shares = self.internal_add_liquidity(pool_id, &mut amounts);
+        assert!(shares >= min_shares.0, "{}", ERR36_SLIPPAGE);
+        shares.into()
     }
 
     #[payable]
     pub fn remove_liquidity(&mut self, pool_id: u64, shares: U128, min_amounts: Vec<U128>) {
         let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
         let amounts = pool.remove_liquidity(
             &sender_id,
-            shares.into(),
+            shares.0,
             min_amounts
                 .into_iter()
                 .map(|amount| amount.into())
                 .collect(),
         );
-        self.pools.replace(pool_id, &pool);
-        let tokens = pool.tokens();
-        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
-        for i in 0..tokens.len() {
-            deposits.deposit(&tokens[i], amounts[i]);
+        let tokens = pool.tokens().to_vec();
+        self.internal_save_pool(pool_id, pool);
+        self.internal_deposit_withdrawn_liquidity(&sender_id, &tokens, &amounts, prev_storage);
+        Event::RemoveLiquidity {
+            account_id: &sender_id,
+            pool_id,
+            amounts: amounts.into_iter().map(U128).collect(),
+            shares,
+        }
+        .emit();
+    }
+
+    /// Removes given amounts of tokens from the stable swap pool, imbalance is charged with fee.
+    /// Fails if more than `max_burn_shares` would be burned. Returns number of burned shares.
+    /// As `remove_liquidity`, works while the contract is paused or the pool is frozen.
+    #[payable]
+    pub fn remove_liquidity_by_tokens(
+        &mut self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        max_burn_shares: U128,
+    ) -> U128 {
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        let burned_shares = pool.remove_liquidity_by_tokens(
+            &sender_id,
+            amounts.clone(),
+            max_burn_shares.into(),
+            &self.internal_admin_fees(None),
+        );
+        let tokens = pool.tokens().to_vec();
+        self.internal_save_pool(pool_id, pool);
+        self.internal_deposit_withdrawn_liquidity(&sender_id, &tokens, &amounts, prev_storage);
+        Event::RemoveLiquidity {
+            account_id: &sender_id,
+            pool_id,
+            amounts: amounts.into_iter().map(U128).collect(),
+            shares: U128(burned_shares),
         }
-        if prev_storage > env::storage_usage() {
-            deposits.near_amount +=
-                (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        .emit();
+        burned_shares.into()
+    }
+
+    /// Executes given list of swap actions against the caller's internal deposits.
+    /// An action without `amount_in` takes the output of the previous action, so a route
+    /// A->B->C only withdraws A from and deposits C into the caller's account.
+    /// Part of every pool's fee goes to the exchange and, if given, to `referral_id`.
+    /// Returns amount of `token_out` received by the last action.
+    #[payable]
+    pub fn swap(&mut self, actions: Vec<SwapAction>, referral_id: Option<ValidAccountId>) -> U128 {
+        assert_one_yocto();
+        self.assert_contract_running();
+        assert!(!actions.is_empty(), "{}", ERR40_AT_LEAST_ONE_SWAP);
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&sender_id);
+        let admin_fee = self.internal_admin_fees(referral_id);
+        // Output of the last executed action that was not yet settled with the account.
+        let mut pending: Option<(AccountId, Balance)> = None;
+        for action in actions.iter() {
+            let token_in: AccountId = action.token_in.clone().into();
+            let amount_in = match action.amount_in {
+                Some(amount_in) => {
+                    if let Some((token_id, amount)) = pending.take() {
+                        account.deposit(&token_id, amount);
+                    }
+                    account.withdraw(&token_in, amount_in.0);
+                    amount_in.0
+                }
+                None => {
+                    let (token_id, amount) = pending.take().expect(ERR42_NO_PREV_AMOUNT);
+                    assert_eq!(token_id, token_in, "{}", ERR43_WRONG_CHAINED_TOKEN);
+                    amount
+                }
+            };
+            pending = Some(self.internal_execute_swap(&sender_id, action, amount_in, &admin_fee));
         }
-        self.internal_save_account(&sender_id, deposits);
+        let (token_out, amount_out) = pending.expect(ERR40_AT_LEAST_ONE_SWAP);
+        account.deposit(&token_out, amount_out);
+        self.internal_save_account(&sender_id, account, prev_storage);
+        amount_out.into()
     }
 
+    /// Registers given tokens for the sender and adds them to the sender's whitelist,
+    /// so deposits of these tokens are accepted even if they are not globally whitelisted.
     #[payable]
     pub fn register_tokens(&mut self, token_ids: Vec<ValidAccountId>) {
+        let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
         let mut account = self.internal_unwrap_account(&sender_id);
         account.register(&token_ids);
-        self.internal_save_account(&sender_id, account);
+        self.internal_save_account(&sender_id, account, prev_storage);
+        Event::RegisterTokens {
+            account_id: &sender_id,
+            token_ids: &token_ids,
+        }
+        .emit();
     }
 
+    /// Unregisters given tokens and removes them from the sender's whitelist.
+    /// Fails if any of the tokens has non zero balance.
     #[payable]
     pub fn unregister_tokens(&mut self, token_ids: Vec<ValidAccountId>) {
+        let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
         let mut account = self.internal_unwrap_account(&sender_id);
-        for token_id in token_ids {
+        for token_id in token_ids.iter() {
             account.unregister(token_id.as_ref())
         }
-        self.internal_save_account(&sender_id, account)
+        self.internal_save_account(&sender_id, account, prev_storage);
+        Event::UnregisterTokens {
+            account_id: &sender_id,
+            token_ids: &token_ids,
+        }
+        .emit();
     }
 
     pub fn get_deposits(&self, account_id: ValidAccountId) -> HashMap<AccountId, U128> {
@@ -331,6 +627,7 @@ impl Contract {
         unregister: Option<bool>,
     ) -> Promise {
         let token_id: AccountId = token_id.into();
+        let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
         let mut account = self.internal_unwrap_account(&sender_id);
         
@@ -346,10 +643,51 @@ impl Contract {
         if unregister == Some(true) {
             account.unregister(&token_id);
         }
-        self.internal_save_account(&sender_id, account);
+        self.internal_save_account(&sender_id, account, prev_storage);
+        Event::Withdraw {
+            account_id: &sender_id,
+            token_id: &token_id,
+            amount: U128(amount),
+        }
+        .emit();
         self.internal_send_tokens(&sender_id, &token_id, amount)
     }
 
+    /// Fetches current rate of given token from its contract and caches it in every rated pool
+    /// containing it. Can be called by anyone.
+    pub fn update_token_rate(&mut self, token_id: ValidAccountId) -> Promise {
+        assert!(self.rated_pools.contains_key(token_id.as_ref()), "{}", ERR68_TOKEN_NOT_RATED);
+        ext_rate_source::ft_price(token_id.as_ref(), 0, GAS_FOR_RATE_QUERY).then(
+            ext_self::callback_update_token_rate(
+                token_id.into(),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_RATE_CALLBACK,
+            ),
+        )
+    }
+
+    #[private]
+    pub fn callback_update_token_rate(&mut self, token_id: AccountId, #[callback] rate: U128) {
+        let mut pool_ids = vec![];
+        for pool_id in self.rated_pools.get(&token_id).unwrap_or_default() {
+            if let Some(Pool::RatedSwapPool(mut pool)) = self.internal_get_pool(pool_id) {
+                if pool.set_rate(&token_id, rate.0) {
+                    self.internal_save_pool(pool_id, Pool::RatedSwapPool(pool));
+                    pool_ids.push(pool_id);
+                }
+            }
+        }
+        if !pool_ids.is_empty() {
+            Event::UpdateTokenRate {
+                token_id: &token_id,
+                rate,
+                pool_ids: &pool_ids,
+            }
+            .emit();
+        }
+    }
+
     #[private]
     pub fn exchange_callback_post_withdraw(
         &mut self,
@@ -374,86 +712,176 @@ impl Contract {
                     if account.deposit_with_storage_check(&token_id, amount.0) {
                         // cause storage already checked, here can directly save
                         self.accounts.insert(&sender_id, &account.into());
+                        Event::WithdrawRefund {
+                            account_id: &sender_id,
+                            token_id: &token_id,
+                            amount,
+                        }
+                        .emit();
                     } else {
                         // we can ensure that internal_get_account here would NOT cause a version upgrade, 
                         // cause it is callback, the account must be the current version or non-exist,
                         // so, here we can just leave it without insert, won't cause storage collection inconsistency.
-                        env::log(
-                            format!(
-                                "Account {} has not enough storage. Depositing to owner.",
-                                sender_id
-                            )
-                            .as_bytes(),
-                        );
                         failed = true;
                     }
                 } else {
-                    env::log(
-                        format!(
-                            "Account {} is not registered. Depositing to owner.",
-                            sender_id
-                        )
-                        .as_bytes(),
-                    );
                     failed = true;
                 }
                 if failed {
                     self.internal_lostfound(&token_id, amount.0);
+                    Event::Lostfound {
+                        account_id: &sender_id,
+                        token_id: &token_id,
+                        amount,
+                    }
+                    .emit();
                 }
             }
         };
     }
 }
 
-#[near_bindgen]
-impl FungibleTokenReceiver for Contract {
-    fn ft_on_transfer(
-        &mut self,
-        sender_id: ValidAccountId,
-        amount: U128,
-        msg: String,
-    ) -> PromiseOrValue<U128> {
-        let token_id = env::predecessor_account_id();
-        assert!(msg.is_empty(), "msg must empty on deposit action");
-        self.internal_save_information_to_contract(&sender_id.into(), &token_id, amount.into());
-        PromiseOrValue::Value(U128(0))
-        
-    }
-}
-
 impl Contract {
-    pub fn internal_save_account(&mut self, account_id: &AccountId, account: Account) {
+    /// Saves the account, charging storage taken or released since `prev_storage` to it.
+    /// All storage changes of the account, including its LP shares, must happen after `prev_storage`.
+    pub fn internal_save_account(&mut self, account_id: &AccountId, account: Account, prev_storage: StorageUsage) {
+        // Write the record first, so a new or upgraded record and its registry entry are measured too.
+        // `storage_used` has fixed size, so writing it again doesn't change the usage.
+        let is_legacy = self.legacy_accounts.contains_key(account_id);
+        let before_save = env::storage_usage();
+        self.account_ids.insert(account_id);
+        self.internal_untrack_legacy_account(account_id);
+        let v_account: VAccount = account.into();
+        self.accounts.insert(account_id, &v_account);
+        let mut account: Account = v_account.into();
+        if is_legacy {
+            // Legacy accounts are grandfathered: the contract covers moving their record.
+            account.update_storage_used(
+                (prev_storage + env::storage_usage()).saturating_sub(before_save),
+            );
+        } else {
+            account.update_storage_used(prev_storage);
+        }
         account.assert_storage_usage();
-        self.accounts.insert(&account_id, &account.into());
+        self.accounts.insert(account_id, &account.into());
     }
 
     /// save token to owner account as lostfound, no need to care about storage
-    /// only global whitelisted token can be stored in lost-found
+    /// any token is accepted, as it may be only whitelisted by the user it failed to reach
     pub(crate) fn internal_lostfound(&mut self, token_id: &AccountId, amount: u128) {
-        if self.whitelisted_tokens.contains(token_id) {
-            let mut lostfound = self.internal_unwrap_or_default_account(&self.owner_id);
-            lostfound.deposit(token_id, amount);
-            self.accounts.insert(&self.owner_id, &lostfound.into());
+        let prev_storage = env::storage_usage();
+        let mut lostfound = self.internal_unwrap_or_default_account(&self.owner_id);
+        lostfound.deposit(token_id, amount);
+        self.account_ids.insert(&self.owner_id);
+        self.internal_untrack_legacy_account(&self.owner_id.clone());
+        lostfound.update_storage_used(prev_storage);
+        self.accounts.insert(&self.owner_id, &lostfound.into());
+    }
+
+    pub(crate) fn assert_contract_running(&self) {
+        assert_eq!(self.state, RunningState::Running, "{}", ERR94_CONTRACT_PAUSED);
+    }
+
+    /// Returns `Paused` if given pool is frozen or contains a frozen token.
+    pub(crate) fn internal_pool_state(&self, pool_id: u64, pool: &Pool) -> RunningState {
+        if self.frozen_pools.contains(&pool_id)
+            || pool.tokens().iter().any(|token_id| self.frozen_tokens.contains(token_id))
+        {
+            RunningState::Paused
         } else {
-            env::panic("ERR: non-whitelisted token can NOT deposit into lost-found.".as_bytes());
+            RunningState::Running
         }
-        
+    }
+
+    pub(crate) fn assert_pool_running(&self, pool_id: u64, pool: &Pool) {
+        assert_eq!(
+            self.internal_pool_state(pool_id, pool),
+            RunningState::Running,
+            "{}", ERR86_POOL_FROZEN
+        );
     }
 
     pub fn internal_get_account(&self, account_id: &AccountId) -> Option<Account> {
         self.accounts
             .get(account_id)
+            .map(|v_account| v_account.into())
+            .or_else(|| {
+                self.legacy_accounts
+                    .get(account_id)
+                    .map(|account| account.into_current(account_id))
+            })
+    }
+
+    /// Records that given account has a shares entry in given pool.
+    pub(crate) fn internal_track_pool(&mut self, account_id: &AccountId, pool_id: u64) {
+        let mut pool_ids = self.account_pools.get(account_id).unwrap_or_default();
+        if !pool_ids.contains(&pool_id) {
+            pool_ids.push(pool_id);
+            self.account_pools.insert(account_id, &pool_ids);
+        }
+    }
+
+    /// Returns ids of pools given account has a shares entry in.
+    pub(crate) fn internal_get_account_pools(&self, account_id: &AccountId) -> Vec<u64> {
+        let mut pool_ids = self.account_pools.get(account_id).unwrap_or_default();
+        if self.legacy_accounts.contains_key(account_id) {
+            // Entries from before the upgrade were not tracked, they can only be in the pools of that time.
+            for pool_id in 0..self.legacy_pool_count {
+                if !pool_ids.contains(&pool_id)
+                    && self.internal_get_pool(pool_id).unwrap().share_is_registered(account_id)
+                {
+                    pool_ids.push(pool_id);
+                }
+            }
+        }
+        pool_ids
+    }
+
+    /// Removes the legacy record of given account, tracking its shares entries from before the upgrade.
+    fn internal_untrack_legacy_account(&mut self, account_id: &AccountId) {
+        if self.legacy_accounts.contains_key(account_id) {
+            let pool_ids = self.internal_get_account_pools(account_id);
+            if !pool_ids.is_empty() {
+                self.account_pools.insert(account_id, &pool_ids);
+            }
+            self.legacy_accounts.remove(account_id);
+        }
+    }
+
+    /// Returns true if given account is registered, in the current or legacy layout.
+    pub(crate) fn internal_is_registered(&self, account_id: &AccountId) -> bool {
+        self.accounts.contains_key(account_id) || self.legacy_accounts.contains_key(account_id)
+    }
+
+    /// Returns given pool, converted to the current pool layout.
+    pub(crate) fn internal_get_pool(&self, pool_id: u64) -> Option<Pool> {
+        self.pools.get(pool_id).map(|v_pool| v_pool.into())
+    }
+
+    pub(crate) fn internal_save_pool(&mut self, pool_id: u64, pool: Pool) {
+        self.pools.replace(pool_id, &pool.into());
     }
 
     pub fn internal_unwrap_account(&self, account_id: &AccountId) -> Account {
         self.internal_get_account(account_id)
-            .expect("ACCOUNT NOT REGISTERED")
+            .expect(ERR10_ACC_NOT_REGISTERED)
+    }
+
+    /// Returns true if given token is whitelisted globally or by given user,
+    /// or the user already has a deposit entry of it.
+    pub(crate) fn internal_is_token_accepted(&self, account_id: &AccountId, token_id: &AccountId) -> bool {
+        self.whitelisted_tokens.contains(token_id)
+            || self
+                .internal_get_account(account_id)
+                .map(|account| account.is_whitelisted(token_id) || account.get_balance(token_id).is_some())
+                .unwrap_or(false)
     }
 
     pub fn internal_register_account(&mut self, account_id: &AccountId, amount: Balance) {
-        let mut account = self.internal_unwrap_or_default_account(&account_id);
+        let prev_storage = env::storage_usage();
+        let mut account = self.internal_unwrap_or_default_account(account_id);
         account.near_amount += amount;
-        self.internal_save_account(&account_id, account);
+        self.internal_save_account(account_id, account, prev_storage);
     }
 
     pub fn internal_unwrap_or_default_account (&self, account_id: &AccountId) -> Account {
@@ -462,16 +890,17 @@ impl Contract {
     }
 
     pub fn internal_storage_withdraw(&mut self, account_id: &AccountId, amount: Balance) -> u128 {
-        let mut account = self.internal_unwrap_account(&account_id);
+        let prev_storage = env::storage_usage();
+        let mut account = self.internal_unwrap_account(account_id);
         let available = account.storage_available();
-        assert!(available > 0, "Not storage withdraw");
+        assert!(available > 0, "{}", ERR15_NO_STORAGE_CAN_WITHDRAW);
         let mut withdraw_amount = amount;
         if amount == 0 {
             withdraw_amount = available;
         }
-        assert!(withdraw_amount <= available, "storage withdraw to much");
+        assert!(withdraw_amount <= available, "{}", ERR16_STORAGE_WITHDRAW_TOO_MUCH);
         account.near_amount -= withdraw_amount;
-        self.internal_save_account(account_id, account);
+        self.internal_save_account(account_id, account, prev_storage);
         withdraw_amount
     }
 
@@ -485,8 +914,8 @@ impl Contract {
             .checked_sub(storage_cost)
             .expect(
                     format!(
-                        "ERR_STORAGE_DEPOSIT need {}, attatched {}",
-                        storage_cost, env::attached_deposit()
+                        "{} need {}, attached {}",
+                        ERR17_DEPOSIT_LESS_THAN_STORAGE_COST, storage_cost, env::attached_deposit()
                     ).as_str()
             );
         if refund > 0 {
@@ -494,15 +923,147 @@ impl Contract {
         }
     }
 
+    /// Adds given amounts from sender's deposits to the pool, amounts are updated to what was actually taken.
+    /// Attached deposit is added to sender's storage balance, which pays for its LP shares entry.
+    /// Returns number of minted shares.
+    fn internal_add_liquidity(&mut self, pool_id: u64, amounts: &mut [Balance]) -> Balance {
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        self.assert_pool_running(pool_id, &pool);
+        let shares = pool.add_liquidity(
+            &sender_id,
+            amounts,
+            &self.internal_admin_fees(None),
+        );
+        self.internal_track_pool(&sender_id, pool_id);
+        let mut deposits = self.internal_unwrap_account(&sender_id);
+        deposits.near_amount += env::attached_deposit();
+        let tokens = pool.tokens();
+        for i in 0..tokens.len() {
+            deposits.withdraw(&tokens[i], amounts[i]);
+        }
+        self.internal_save_account(&sender_id, deposits, prev_storage);
+        self.internal_save_pool(pool_id, pool);
+        Event::AddLiquidity {
+            account_id: &sender_id,
+            pool_id,
+            amounts: amounts.iter().map(|amount| U128(*amount)).collect(),
+            shares: U128(shares),
+        }
+        .emit();
+        shares
+    }
+
+    /// Deposits amounts withdrawn from a pool to sender's account,
+    /// storage released by the pool is released from the account's storage used.
+    fn internal_deposit_withdrawn_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        tokens: &[AccountId],
+        amounts: &[Balance],
+        prev_storage: StorageUsage,
+    ) {
+        let mut deposits = self.internal_unwrap_account(sender_id);
+        for i in 0..tokens.len() {
+            deposits.deposit(&tokens[i], amounts[i]);
+        }
+        self.internal_save_account(sender_id, deposits, prev_storage);
+    }
+
+    /// Returns fees configuration of the exchange for a swap with given referral.
+    fn internal_admin_fees(&self, referral_id: Option<ValidAccountId>) -> AdminFees {
+        AdminFees::new(
+            self.exchange_fee,
+            self.referral_fee,
+            env::current_account_id(),
+            referral_id.map(|id| id.into()),
+        )
+    }
+
+    /// Executes single swap action with given input amount.
+    /// Returns token and amount received.
+    fn internal_execute_swap(
+        &mut self,
+        account_id: &AccountId,
+        action: &SwapAction,
+        amount_in: Balance,
+        admin_fee: &AdminFees,
+    ) -> (AccountId, Balance) {
+        let token_out: AccountId = action.token_out.clone().into();
+        let amount_out = self.internal_pool_swap(
+            action.pool_id,
+            action.token_in.as_ref(),
+            amount_in,
+            &token_out,
+            action.min_amount_out.0,
+            admin_fee,
+        );
+        Event::Swap {
+            account_id,
+            pool_id: action.pool_id,
+            token_in: action.token_in.as_ref(),
+            amount_in: U128(amount_in),
+            token_out: &token_out,
+            amount_out: U128(amount_out),
+            referral_id: admin_fee.referral_id.as_ref(),
+        }
+        .emit();
+        (token_out, amount_out)
+    }
+
+    /// Swaps given amount through the given pool and returns received amount.
+    /// Balances of the caller must be handled outside.
+    fn internal_pool_swap(
+        &mut self,
+        pool_id: u64,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        self.assert_pool_running(pool_id, &pool);
+        let amount_out = pool.swap(token_in, amount_in, token_out, min_amount_out, admin_fee);
+        self.internal_save_pool(pool_id, pool);
+        amount_out
+    }
+
     /// Adds given pool to the list and returns it's id.
     /// If there is not enough attached balance to cover storage, fails.
     /// If too much attached - refunds it back.
+    /// Fails on non-whitelisted tokens if pools are restricted to whitelisted tokens.
     fn internal_add_pool(&mut self, mut pool: Pool) -> u64 {
+        self.assert_contract_running();
+        if self.whitelisted_pool_tokens_only {
+            for token_id in pool.tokens() {
+                assert!(
+                    self.whitelisted_tokens.contains(token_id),
+                    "{}", ERR20_TOKEN_NOT_WHITELISTED
+                );
+            }
+        }
         let prev_storage = env::storage_usage();
         let id = self.pools.len() as u64;
         // exchange share was registered at creation time
         pool.share_register(&env::current_account_id());
-        self.pools.push(&pool);
+        Event::CreatePool {
+            account_id: &env::predecessor_account_id(),
+            pool_id: id,
+            pool_kind: pool.kind(),
+            token_ids: pool.tokens(),
+        }
+        .emit();
+        if let Pool::RatedSwapPool(pool) = &pool {
+            for token_id in pool.rated_tokens() {
+                let mut pool_ids = self.rated_pools.get(token_id).unwrap_or_default();
+                pool_ids.push(id);
+                self.rated_pools.insert(token_id, &pool_ids);
+            }
+        }
+        self.pools.push(&pool.into());
         self.internal_check_storage(prev_storage);
         id
     }
@@ -512,16 +1073,23 @@ impl Contract {
         account_id: &AccountId,
         token_id: &AccountId,
         amount: Balance) {
-            let mut account = self.internal_unwrap_or_default_account(account_id);
+            let prev_storage = env::storage_usage();
+            let mut account = self.internal_unwrap_account(account_id);
             let account_amount = account.tokens.get(token_id).unwrap_or_default();
             // assert!(account.tokens.get(token_id).is_none(), "Token has already registered!");
-            assert!(amount > 0, "Amount must be greater than 0!" );
+            assert!(amount > 0, "{}", ERR26_ZERO_DEPOSIT );
             if amount == 0 {
                 account.tokens.insert(token_id, &amount);
             }else {
                 account.tokens.insert(token_id, &(amount + account_amount));
             }
-            self.internal_save_account(account_id, account);
+            self.internal_save_account(account_id, account, prev_storage);
+            Event::Deposit {
+                account_id,
+                token_id,
+                amount: U128(amount),
+            }
+            .emit();
     }
 
     /// Returns balance of the deposit for given user outside of any pools.
@@ -566,3 +1134,467 @@ impl Contract {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain, PromiseOrValue};
+
+    use super::*;
+    use crate::stable_swap::RATE_DIVISOR;
+    use crate::utils::{MIN_LIQUIDITY, U256};
+
+    const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+    fn setup_contract() -> (VMContextBuilder, Contract) {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let contract = Contract::new(accounts(0), 1600, 400);
+        (context, contract)
+    }
+
+    /// Registers the account if needed and deposits given amounts of given tokens to it.
+    fn deposit_tokens(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        account_id: ValidAccountId,
+        token_amounts: Vec<(ValidAccountId, Balance)>,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(account_id.clone())
+            .attached_deposit(ONE_NEAR)
+            .build());
+        if contract.storage_balance_of(account_id.clone()).is_none() {
+            contract.storage_deposit(None, None);
+        }
+        contract.register_tokens(token_amounts.iter().map(|(token_id, _)| token_id.clone()).collect());
+        for (token_id, amount) in token_amounts {
+            testing_env!(context
+                .predecessor_account_id(token_id)
+                .attached_deposit(1)
+                .build());
+            contract.ft_on_transfer(account_id.clone(), U128(amount), "".to_string());
+        }
+    }
+
+    /// Creates simple pool of given tokens and adds given amounts of them to it from `accounts(3)`.
+    fn create_pool_with_liquidity(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        token_amounts: Vec<(ValidAccountId, Balance)>,
+    ) -> u64 {
+        let tokens: Vec<ValidAccountId> = token_amounts.iter().map(|(token_id, _)| token_id.clone()).collect();
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        let pool_id = contract.add_simple_pool(tokens, 25);
+        deposit_tokens(context, contract, accounts(3), token_amounts.clone());
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        contract.add_liquidity(
+            pool_id,
+            token_amounts.into_iter().map(|(_, amount)| U128(amount)).collect(),
+            None,
+        );
+        pool_id
+    }
+
+    /// Creates pools of tokens 1-2 and 2-4 and deposits token 1 to the trader `accounts(5)`.
+    fn setup_route(context: &mut VMContextBuilder, contract: &mut Contract) {
+        create_pool_with_liquidity(context, contract, vec![(accounts(1), 1_000_000), (accounts(2), 2_000_000)]);
+        create_pool_with_liquidity(context, contract, vec![(accounts(2), 2_000_000), (accounts(4), 500_000)]);
+        deposit_tokens(context, contract, accounts(5), vec![(accounts(1), 100_000)]);
+        testing_env!(context
+            .predecessor_account_id(accounts(5))
+            .attached_deposit(1)
+            .build());
+    }
+
+    fn swap_action(
+        pool_id: u64,
+        token_in: ValidAccountId,
+        amount_in: Option<Balance>,
+        token_out: ValidAccountId,
+        min_amount_out: Balance,
+    ) -> SwapAction {
+        SwapAction {
+            pool_id,
+            token_in,
+            amount_in: amount_in.map(U128),
+            token_out,
+            min_amount_out: U128(min_amount_out),
+        }
+    }
+
+    #[test]
+    fn test_swap_multi_hop() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        let first_out = contract.get_return(0, accounts(1), U128(10_000), accounts(2)).0;
+        let second_out = contract.get_return(1, accounts(2), U128(first_out), accounts(4)).0;
+        let amount_out = contract.swap(
+            vec![
+                swap_action(0, accounts(1), Some(10_000), accounts(2), first_out),
+                swap_action(1, accounts(2), None, accounts(4), second_out),
+            ],
+            None,
+        );
+        assert_eq!(amount_out.0, second_out);
+        // Only the route's ends settle with the trader's deposits.
+        assert_eq!(contract.get_token_balance(accounts(5), accounts(1)).0, 90_000);
+        assert_eq!(contract.get_token_balance(accounts(5), accounts(2)).0, 0);
+        assert_eq!(contract.get_token_balance(accounts(5), accounts(4)).0, second_out);
+        assert_eq!(
+            contract.get_pool(1).amounts,
+            vec![U128(2_000_000 + first_out), U128(500_000 - second_out)]
+        );
+    }
+
+    #[test]
+    fn test_deposit_of_registered_token() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        // Token 2 is not whitelisted by the trader, so its deposit is refunded.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let refund = contract.ft_on_transfer(accounts(5), U128(1_000), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+
+        // Swap output registers token 4 in the trader's deposits, so it is accepted since.
+        testing_env!(context
+            .predecessor_account_id(accounts(5))
+            .attached_deposit(1)
+            .build());
+        contract.swap(
+            vec![
+                swap_action(0, accounts(1), Some(10_000), accounts(2), 0),
+                swap_action(1, accounts(2), None, accounts(4), 0),
+            ],
+            None,
+        );
+        let balance = contract.get_token_balance(accounts(5), accounts(4)).0;
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(0).build());
+        let refund = contract.ft_on_transfer(accounts(5), U128(1_000), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+        assert_eq!(contract.get_token_balance(accounts(5), accounts(4)).0, balance + 1_000);
+    }
+
+    #[test]
+    fn test_instant_swap_emits_withdraw() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        let amount_out = contract.get_return(0, accounts(1), U128(10_000), accounts(2)).0;
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let msg = format!(
+            r#"{{"actions": [{{"pool_id": 0, "token_in": "{}", "token_out": "{}", "min_amount_out": "0"}}]}}"#,
+            accounts(1),
+            accounts(2)
+        );
+        contract.ft_on_transfer(accounts(5), U128(10_000), msg);
+        let withdraw = format!(
+            r#""event":"withdraw","data":{{"account_id":"{}","token_id":"{}","amount":"{}"}}"#,
+            accounts(5),
+            accounts(2),
+            amount_out
+        );
+        assert!(near_sdk::test_utils::get_logs().iter().any(|log| log.contains(&withdraw)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E35: amount less than min amount")]
+    fn test_swap_min_amount_out() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        let first_out = contract.get_return(0, accounts(1), U128(10_000), accounts(2)).0;
+        contract.swap(vec![swap_action(0, accounts(1), Some(10_000), accounts(2), first_out + 1)], None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E35: amount less than min amount")]
+    fn test_swap_multi_hop_min_amount_out() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        let first_out = contract.get_return(0, accounts(1), U128(10_000), accounts(2)).0;
+        let second_out = contract.get_return(1, accounts(2), U128(first_out), accounts(4)).0;
+        contract.swap(
+            vec![
+                swap_action(0, accounts(1), Some(10_000), accounts(2), 0),
+                swap_action(1, accounts(2), None, accounts(4), second_out + 1),
+            ],
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E43: chained action takes other token than previous output")]
+    fn test_swap_chain_wrong_token() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        contract.swap(
+            vec![
+                swap_action(0, accounts(1), Some(10_000), accounts(2), 0),
+                swap_action(1, accounts(4), None, accounts(2), 0),
+            ],
+            None,
+        );
+    }
+
+    #[test]
+    fn test_mft_resolve_transfer_after_sender_unregistered() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            vec![(accounts(1), 100_000), (accounts(2), 100_000)],
+        );
+        let token_id = format!(":{}", pool_id);
+        let shares = contract.get_pool_shares(pool_id, accounts(3));
+        testing_env!(context
+            .predecessor_account_id(accounts(5))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        contract.mft_register(token_id.clone(), accounts(5));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.mft_transfer_call(token_id.clone(), accounts(5), shares, None, "".to_string());
+        assert!(contract.storage_unregister(Some(true)));
+
+        // Receiver returns all shares unused.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(near_sdk::serde_json::to_vec(&shares).unwrap())]
+        );
+        let used = contract.mft_resolve_transfer(token_id, accounts(3).into(), accounts(5).into(), shares);
+        assert_eq!(used.0, 0);
+        assert_eq!(contract.get_pool_shares(pool_id, accounts(5)).0, 0);
+        let contract_id = ValidAccountId::try_from(env::current_account_id()).unwrap();
+        assert_eq!(contract.get_pool_shares(pool_id, contract_id).0, shares.0 + MIN_LIQUIDITY);
+        assert_eq!(contract.get_pool(pool_id).min_liquidity.0, MIN_LIQUIDITY);
+    }
+
+    #[test]
+    fn test_mft_register_charges_account_storage() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            vec![(accounts(1), 100_000), (accounts(2), 100_000)],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(5), vec![(accounts(1), 100)]);
+        let user: AccountId = accounts(5).into();
+        let account = contract.internal_unwrap_account(&user);
+        let prev_storage = env::storage_usage();
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        contract.mft_register(format!(":{}", pool_id), accounts(5));
+        let storage_used = env::storage_usage() - prev_storage;
+        assert!(storage_used > 0);
+        let registered = contract.internal_unwrap_account(&user);
+        assert_eq!(registered.storage_used, account.storage_used + storage_used);
+        assert_eq!(registered.near_amount, account.near_amount + ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "E73: not enough gas attached to transfer call")]
+    fn test_mft_transfer_call_not_enough_gas() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            vec![(accounts(1), 100_000), (accounts(2), 100_000)],
+        );
+        let token_id = format!(":{}", pool_id);
+        testing_env!(context
+            .predecessor_account_id(accounts(5))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        contract.mft_register(token_id.clone(), accounts(5));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .prepaid_gas(GAS_FOR_MFT_TRANSFER_CALL)
+            .build());
+        contract.mft_transfer_call(token_id, accounts(5), U128(1), None, "".to_string());
+    }
+
+    #[test]
+    fn test_update_token_rate_of_all_rated_pools() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        for rated_token in [accounts(2), accounts(1)] {
+            contract.add_rated_swap_pool(vec![accounts(1), accounts(2)], vec![24, 24], vec![rated_token], 25, 100);
+        }
+        contract.add_rated_swap_pool(vec![accounts(2), accounts(4)], vec![24, 24], vec![accounts(2)], 25, 100);
+        contract.add_simple_pool(vec![accounts(1), accounts(2)], 25);
+
+        testing_env!(context
+            .predecessor_account_id(ValidAccountId::try_from(env::current_account_id()).unwrap())
+            .attached_deposit(0)
+            .block_timestamp(1_000_000_000)
+            .build());
+        contract.callback_update_token_rate(accounts(2).into(), U128(2 * RATE_DIVISOR));
+        for (pool_id, rates) in [
+            (0, vec![RATE_DIVISOR, 2 * RATE_DIVISOR]),
+            (2, vec![2 * RATE_DIVISOR, RATE_DIVISOR]),
+        ] {
+            match contract.internal_get_pool(pool_id).unwrap() {
+                Pool::RatedSwapPool(pool) => assert_eq!(pool.current_rates(), rates),
+                _ => unreachable!(),
+            }
+        }
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("\"pool_ids\":[0,2]"));
+
+        // Nothing is logged if no pool was updated.
+        testing_env!(context.build());
+        contract.callback_update_token_rate(accounts(4).into(), U128(2 * RATE_DIVISOR));
+        assert!(near_sdk::test_utils::get_logs().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "E68: token is not rated in any pool")]
+    fn test_update_rate_of_not_rated_token() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        contract.add_rated_swap_pool(vec![accounts(1), accounts(2)], vec![24, 24], vec![accounts(2)], 25, 100);
+        contract.update_token_rate(accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "E27: storage unregister with non-empty LP shares")]
+    fn test_unregister_with_shares() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            vec![(accounts(1), 100_000), (accounts(2), 100_000)],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn test_force_unregister_with_shares() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        assert!(contract.storage_unregister(Some(true)));
+
+        // Shares are burned down to the locked ones and the removed liquidity is sent out.
+        let user: AccountId = accounts(3).into();
+        for pool_id in 0..2 {
+            assert_eq!(contract.get_pool_total_shares(pool_id).0, MIN_LIQUIDITY);
+            assert!(!contract.internal_get_pool(pool_id).unwrap().share_is_registered(&user));
+        }
+        assert!(contract.account_pools.get(&user).is_none());
+        assert!(contract.storage_balance_of(accounts(3)).is_none());
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.iter().filter(|log| log.contains("\"event\":\"remove_liquidity\"")).count(), 2);
+        assert_eq!(logs.iter().filter(|log| log.contains("\"event\":\"withdraw\"")).count(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "E80: too many tokens to send on force unregister")]
+    fn test_force_unregister_too_many_tokens() {
+        let (mut context, mut contract) = setup_contract();
+        let tokens = (0..7)
+            .map(|i| (ValidAccountId::try_from(format!("token{}.near", i)).unwrap(), 100))
+            .collect();
+        deposit_tokens(&mut context, &mut contract, accounts(5), tokens);
+        testing_env!(context
+            .predecessor_account_id(accounts(5))
+            .attached_deposit(1)
+            .build());
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    fn test_unregister_removes_share_entries() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            vec![(accounts(1), 100_000), (accounts(2), 100_000)],
+        );
+        let shares = contract.get_pool_shares(pool_id, accounts(3));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.remove_liquidity(pool_id, shares, vec![U128(0), U128(0)]);
+        assert!(contract.storage_unregister(Some(true)));
+
+        let user: AccountId = accounts(3).into();
+        assert!(!contract.internal_get_pool(pool_id).unwrap().share_is_registered(&user));
+        assert!(contract.account_pools.get(&user).is_none());
+        assert!(contract.storage_balance_of(accounts(3)).is_none());
+        assert_eq!(contract.get_user_count(), 0);
+    }
+
+    #[test]
+    fn test_get_user_pools() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        assert_eq!(contract.get_user_pools(accounts(3), 0, 10), vec![0, 1]);
+        assert_eq!(contract.get_user_pools(accounts(3), 1, 10), vec![1]);
+        assert_eq!(contract.get_user_pools(accounts(3), 0, 1), vec![0]);
+        assert!(contract.get_user_pools(accounts(5), 0, 10).is_empty());
+
+        // Pools where all shares were removed are skipped before paging.
+        let shares = contract.get_pool_shares(0, accounts(3));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.remove_liquidity(0, shares, vec![U128(0), U128(0)]);
+        assert_eq!(contract.get_user_pools(accounts(3), 0, 1), vec![1]);
+    }
+
+    #[test]
+    fn test_get_user_portfolio() {
+        let (mut context, mut contract) = setup_contract();
+        setup_route(&mut context, &mut contract);
+        let portfolio = contract.get_user_portfolio(accounts(3));
+        assert_eq!(
+            portfolio.pools.iter().map(|pool| pool.pool_id).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        let shares = contract.get_pool_shares(0, accounts(3));
+        assert_eq!(portfolio.pools[0].shares, shares);
+        let total_shares = contract.get_pool_total_shares(0).0;
+        assert_eq!(
+            portfolio.pools[0].amounts,
+            vec![
+                U128((U256::from(1_000_000) * U256::from(shares.0) / U256::from(total_shares)).as_u128()),
+                U128((U256::from(2_000_000) * U256::from(shares.0) / U256::from(total_shares)).as_u128()),
+            ]
+        );
+
+        let portfolio = contract.get_user_portfolio(accounts(5));
+        assert!(portfolio.pools.is_empty());
+        assert_eq!(portfolio.deposits.get(accounts(1).as_ref()), Some(&U128(100_000)));
+    }
+}
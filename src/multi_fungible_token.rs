@@ -0,0 +1,208 @@
+//! Exposes LP shares of the pools as multi fungible tokens, token id of the shares is ":<pool_id>".
+
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_sdk::{ext_contract, PromiseOrValue};
+
+use crate::events::Event;
+use crate::*;
+
+#[ext_contract(ext_share_token_receiver)]
+pub trait MFTTokenReceiver {
+    fn mft_on_transfer(
+        &mut self,
+        token_id: String,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+}
+
+/// Parses ":<pool_id>" token id into pool id.
+/// ':' is not allowed in account ids, so it can't be confused with a token account.
+fn parse_pool_id(token_id: &str) -> u64 {
+    token_id
+        .strip_prefix(':')
+        .and_then(|pool_id| pool_id.parse::<u64>().ok())
+        .unwrap_or_else(|| env::panic(ERR70_ILLEGAL_TOKEN_ID.as_bytes()))
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the balance of LP shares of the given account. If the account doesn't exist will return `"0"`.
+    pub fn mft_balance_of(&self, token_id: String, account_id: ValidAccountId) -> U128 {
+        self.internal_mft_balance(&token_id, account_id.as_ref()).into()
+    }
+
+    /// Returns the total supply of LP shares of the given pool.
+    pub fn mft_total_supply(&self, token_id: String) -> U128 {
+        self.internal_get_pool(parse_pool_id(&token_id))
+            .expect(ERR85_NO_POOL)
+            .share_total_balance()
+            .into()
+    }
+
+    /// Register LP shares of given pool for given account, so it can receive them.
+    /// If the account is registered in the exchange, attached deposit is added to its storage deposit
+    /// and the shares entry is charged to it, otherwise the entry is paid by the attached deposit.
+    #[payable]
+    pub fn mft_register(&mut self, token_id: String, account_id: ValidAccountId) {
+        let prev_storage = env::storage_usage();
+        let pool_id = parse_pool_id(&token_id);
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        pool.share_register(account_id.as_ref());
+        self.internal_save_pool(pool_id, pool);
+        self.internal_track_pool(account_id.as_ref(), pool_id);
+        if let Some(mut account) = self.internal_get_account(account_id.as_ref()) {
+            account.near_amount += env::attached_deposit();
+            self.internal_save_account(account_id.as_ref(), account, prev_storage);
+        } else {
+            self.internal_check_storage(prev_storage);
+        }
+    }
+
+    /// Transfer LP shares of given pool to the receiver, who has to be registered with `mft_register`.
+    #[payable]
+    pub fn mft_transfer(
+        &mut self,
+        token_id: String,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        self.assert_contract_running();
+        self.internal_mft_transfer(
+            &token_id,
+            &env::predecessor_account_id(),
+            receiver_id.as_ref(),
+            amount.0,
+            memo,
+        );
+    }
+
+    /// Transfer LP shares to the receiver and call `mft_on_transfer` on it.
+    /// Unused shares returned by the receiver are refunded back to the sender.
+    #[payable]
+    pub fn mft_transfer_call(
+        &mut self,
+        token_id: String,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        self.assert_contract_running();
+        assert!(env::prepaid_gas() > GAS_FOR_MFT_TRANSFER_CALL, "{}", ERR73_NOT_ENOUGH_GAS);
+        let sender_id = env::predecessor_account_id();
+        self.internal_mft_transfer(&token_id, &sender_id, receiver_id.as_ref(), amount.0, memo);
+        ext_share_token_receiver::mft_on_transfer(
+            token_id.clone(),
+            sender_id.clone(),
+            amount,
+            msg,
+            receiver_id.as_ref(),
+            0,
+            env::prepaid_gas() - GAS_FOR_MFT_TRANSFER_CALL,
+        )
+        .then(ext_self::mft_resolve_transfer(
+            token_id,
+            sender_id,
+            receiver_id.into(),
+            amount,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+        .into()
+    }
+
+    /// Refunds unused shares back to the sender, limited by the receiver's current balance.
+    /// If the sender unregistered from the pool meanwhile, the refund goes to this contract.
+    /// Returns how much was used by the receiver.
+    #[private]
+    pub fn mft_resolve_transfer(
+        &mut self,
+        token_id: String,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        let unused_amount = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => {
+                if let Ok(unused_amount) = near_sdk::serde_json::from_slice::<U128>(&value) {
+                    std::cmp::min(amount.0, unused_amount.0)
+                } else {
+                    amount.0
+                }
+            }
+            PromiseResult::Failed => amount.0,
+        };
+        let mut refund_amount = 0;
+        if unused_amount > 0 {
+            let receiver_balance = self.internal_mft_balance(&token_id, &receiver_id);
+            refund_amount = std::cmp::min(receiver_balance, unused_amount);
+            let pool = self.internal_get_pool(parse_pool_id(&token_id)).expect(ERR85_NO_POOL);
+            let refund_id = if pool.share_is_registered(&sender_id) {
+                sender_id
+            } else {
+                env::current_account_id()
+            };
+            if refund_id == receiver_id {
+                refund_amount = 0;
+            }
+            if refund_amount > 0 {
+                self.internal_mft_transfer(&token_id, &receiver_id, &refund_id, refund_amount, None);
+            }
+        }
+        U128(amount.0 - refund_amount)
+    }
+
+    /// Returns metadata of LP shares of the given pool.
+    pub fn mft_metadata(&self, token_id: String) -> FungibleTokenMetadata {
+        let pool_id = parse_pool_id(&token_id);
+        assert!(pool_id < self.pools.len(), "{}", ERR85_NO_POOL);
+        FungibleTokenMetadata {
+            spec: "mft-1.0.0".to_string(),
+            name: format!("ref-pool-{}", pool_id),
+            symbol: format!("REF-POOL-{}", pool_id),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
+    }
+}
+
+impl Contract {
+    fn internal_mft_transfer(
+        &mut self,
+        token_id: &str,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        assert_ne!(sender_id, receiver_id, "{}", ERR71_TRANSFER_TO_SELF);
+        assert!(amount > 0, "{}", ERR72_ZERO_TRANSFER);
+        let pool_id = parse_pool_id(token_id);
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        pool.share_transfer(sender_id, receiver_id, amount);
+        self.internal_save_pool(pool_id, pool);
+        Event::ShareTransfer {
+            pool_id,
+            sender_id,
+            receiver_id,
+            amount: U128(amount),
+            memo: memo.as_deref(),
+        }
+        .emit();
+    }
+
+    fn internal_mft_balance(&self, token_id: &str, account_id: &AccountId) -> Balance {
+        self.internal_get_pool(parse_pool_id(token_id))
+            .expect(ERR85_NO_POOL)
+            .share_balances(account_id)
+    }
+}
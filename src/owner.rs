@@ -0,0 +1,264 @@
+//! Implement all the relevant logic for owner of this contract.
+
+use near_sdk::json_types::WrappedTimestamp;
+
+use crate::events::Event;
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Proposes new owner of the contract, who has to accept ownership with `accept_owner`.
+    /// Only can be called by owner.
+    #[payable]
+    pub fn set_owner(&mut self, owner_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        Event::SetOwner {
+            account_id: &self.owner_id,
+            owner_id: owner_id.as_ref(),
+        }
+        .emit();
+        self.pending_owner_id = Some(owner_id.into());
+    }
+
+    /// Accepts ownership of the contract. Only can be called by the proposed owner.
+    #[payable]
+    pub fn accept_owner(&mut self) {
+        assert_one_yocto();
+        let pending_owner_id = self.pending_owner_id.take().expect(ERR91_NO_PENDING_OWNER);
+        assert_eq!(
+            env::predecessor_account_id(),
+            pending_owner_id,
+            "{}", ERR90_NOT_ALLOWED
+        );
+        Event::AcceptOwner {
+            account_id: &pending_owner_id,
+        }
+        .emit();
+        self.owner_id = pending_owner_id;
+    }
+
+    /// Extend guardians. Only can be called by owner.
+    #[payable]
+    pub fn extend_guardians(&mut self, guardians: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        Event::ExtendGuardians {
+            account_id: &self.owner_id,
+            guardians: &guardians,
+        }
+        .emit();
+        for guardian in guardians {
+            self.guardians.insert(guardian.as_ref());
+        }
+    }
+
+    /// Remove guardians. Only can be called by owner.
+    #[payable]
+    pub fn remove_guardians(&mut self, guardians: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        Event::RemoveGuardians {
+            account_id: &self.owner_id,
+            guardians: &guardians,
+        }
+        .emit();
+        for guardian in guardians {
+            let exist = self.guardians.remove(guardian.as_ref());
+            assert!(exist, "{}", ERR92_GUARDIAN_NOT_IN_LIST);
+        }
+    }
+
+    /// Changes running state of the contract, e.g. pauses it in emergency. Can be called by owner or guardians.
+    #[payable]
+    pub fn change_state(&mut self, state: RunningState) {
+        assert_one_yocto();
+        self.assert_owner_or_guardians();
+        if self.state != state {
+            Event::ChangeState {
+                account_id: &env::predecessor_account_id(),
+                state: &state,
+            }
+            .emit();
+            self.state = state;
+        }
+    }
+
+    /// Freezes given pools, so they reject swaps and liquidity adds. Can be called by owner or guardians.
+    #[payable]
+    pub fn freeze_pools(&mut self, pool_ids: Vec<u64>) {
+        assert_one_yocto();
+        self.assert_owner_or_guardians();
+        Event::FreezePools {
+            account_id: &env::predecessor_account_id(),
+            pool_ids: &pool_ids,
+        }
+        .emit();
+        for pool_id in pool_ids {
+            assert!(pool_id < self.pools.len(), "{}", ERR85_NO_POOL);
+            self.frozen_pools.insert(&pool_id);
+        }
+    }
+
+    /// Unfreezes given pools. Only can be called by owner.
+    #[payable]
+    pub fn unfreeze_pools(&mut self, pool_ids: Vec<u64>) {
+        assert_one_yocto();
+        self.assert_owner();
+        Event::UnfreezePools {
+            account_id: &self.owner_id,
+            pool_ids: &pool_ids,
+        }
+        .emit();
+        for pool_id in pool_ids {
+            let exist = self.frozen_pools.remove(&pool_id);
+            assert!(exist, "{}", ERR87_POOL_NOT_FROZEN);
+        }
+    }
+
+    /// Freezes given tokens, so all pools containing them reject swaps and liquidity adds.
+    /// Can be called by owner or guardians.
+    #[payable]
+    pub fn extend_frozen_tokens(&mut self, tokens: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner_or_guardians();
+        Event::ExtendFrozenTokens {
+            account_id: &env::predecessor_account_id(),
+            token_ids: &tokens,
+        }
+        .emit();
+        for token in tokens {
+            self.frozen_tokens.insert(token.as_ref());
+        }
+    }
+
+    /// Unfreezes given tokens. Only can be called by owner.
+    #[payable]
+    pub fn remove_frozen_tokens(&mut self, tokens: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        Event::RemoveFrozenTokens {
+            account_id: &self.owner_id,
+            token_ids: &tokens,
+        }
+        .emit();
+        for token in tokens {
+            let exist = self.frozen_tokens.remove(token.as_ref());
+            assert!(exist, "{}", ERR93_TOKEN_NOT_FROZEN);
+        }
+    }
+
+    /// Starts ramping amplification coefficient of given stable swap pool
+    /// to `future_amp_factor`, reached at `future_amp_time` (in nanoseconds).
+    #[payable]
+    pub fn stable_swap_ramp_amp(
+        &mut self,
+        pool_id: u64,
+        future_amp_factor: u64,
+        future_amp_time: WrappedTimestamp,
+    ) {
+        assert_one_yocto();
+        self.assert_owner();
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        match &mut pool {
+            Pool::StableSwapPool(pool) => {
+                pool.ramp_amplification(future_amp_factor as u128, future_amp_time.0)
+            }
+            Pool::RatedSwapPool(pool) => {
+                pool.pool.ramp_amplification(future_amp_factor as u128, future_amp_time.0)
+            }
+            _ => env::panic(ERR58_NOT_STABLE_POOL.as_bytes()),
+        }
+        self.internal_save_pool(pool_id, pool);
+        Event::RampAmp {
+            account_id: &self.owner_id,
+            pool_id,
+            future_amp_factor,
+            future_amp_time,
+        }
+        .emit();
+    }
+
+    /// Stops ramping amplification coefficient of given stable swap pool at its current value.
+    /// Can be called by owner or guardians.
+    #[payable]
+    pub fn stable_swap_stop_ramp_amp(&mut self, pool_id: u64) {
+        assert_one_yocto();
+        self.assert_owner_or_guardians();
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        match &mut pool {
+            Pool::StableSwapPool(pool) => pool.stop_ramp_amplification(),
+            Pool::RatedSwapPool(pool) => pool.pool.stop_ramp_amplification(),
+            _ => env::panic(ERR58_NOT_STABLE_POOL.as_bytes()),
+        }
+        self.internal_save_pool(pool_id, pool);
+        Event::StopRampAmp {
+            account_id: &env::predecessor_account_id(),
+            pool_id,
+        }
+        .emit();
+    }
+
+    /// Extend whitelisted tokens with new tokens. Only can be called by owner.
+    #[payable]
+    pub fn extend_whitelisted_tokens(&mut self, tokens: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        Event::ExtendWhitelistedTokens {
+            account_id: &self.owner_id,
+            token_ids: &tokens,
+        }
+        .emit();
+        for token in tokens {
+            self.whitelisted_tokens.insert(token.as_ref());
+        }
+    }
+
+    /// Remove whitelisted token. Only can be called by owner.
+    #[payable]
+    pub fn remove_whitelisted_tokens(&mut self, tokens: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        Event::RemoveWhitelistedTokens {
+            account_id: &self.owner_id,
+            token_ids: &tokens,
+        }
+        .emit();
+        for token in tokens {
+            let exist = self.whitelisted_tokens.remove(token.as_ref());
+            assert!(exist, "{}", ERR20_TOKEN_NOT_WHITELISTED);
+        }
+    }
+
+    /// Sets whether new pools can only be created with whitelisted tokens. Only can be called by owner.
+    #[payable]
+    pub fn set_whitelisted_pool_tokens_only(&mut self, enabled: bool) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.whitelisted_pool_tokens_only = enabled;
+        Event::SetWhitelistedPoolTokensOnly {
+            account_id: &self.owner_id,
+            enabled,
+        }
+        .emit();
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "{}", ERR90_NOT_ALLOWED
+        );
+    }
+
+    /// Guardians can perform emergency actions, but can not change owner, guardians or fees.
+    pub(crate) fn assert_owner_or_guardians(&self) {
+        let predecessor_id = env::predecessor_account_id();
+        assert!(
+            predecessor_id == self.owner_id || self.guardians.contains(&predecessor_id),
+            "{}", ERR90_NOT_ALLOWED
+        );
+    }
+}
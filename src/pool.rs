@@ -1,42 +1,115 @@
-use near_sdk::{AccountId, Balance};
+use near_sdk::{env, AccountId, Balance};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 
+use crate::admin_fee::AdminFees;
+use crate::errors::*;
+use crate::rated_swap::RatedSwapPool;
 use crate::simple_pool::SimplePool;
+use crate::stable_swap::StableSwapPool;
 use crate::utils::SwapVolume;
+use crate::weighted_pool::WeightedPool;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(BorshSerialize, BorshDeserialize)]
 pub enum Pool {
     SimplePool(SimplePool),
+    StableSwapPool(StableSwapPool),
+    WeightedPool(WeightedPool),
+    RatedSwapPool(RatedSwapPool),
+}
+
+/// Versioned pool, stored in the contract's pools.
+/// Pools stored with older layouts are converted to the current one when read.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VPool {
+    Current(Pool),
+}
+
+impl From<VPool> for Pool {
+    fn from(v_pool: VPool) -> Self {
+        match v_pool {
+            VPool::Current(pool) => pool,
+        }
+    }
+}
+
+impl From<Pool> for VPool {
+    fn from(pool: Pool) -> Self {
+        VPool::Current(pool)
+    }
 }
 
 impl Pool {
     /// Returns pool kind.
     pub fn kind(&self) -> String {
         match self {
-            Pool::SimplePool(_) => "SIMPLE_POOL".to_string()
+            Pool::SimplePool(_) => "SIMPLE_POOL".to_string(),
+            Pool::StableSwapPool(_) => "STABLE_SWAP".to_string(),
+            Pool::WeightedPool(_) => "WEIGHTED_POOL".to_string(),
+            Pool::RatedSwapPool(_) => "RATED_SWAP".to_string(),
         }
     }
 
 
     pub fn share_register(&mut self, account_id: &AccountId) {
         match self {
-            Pool::SimplePool(pool) => pool.share_register(account_id)
+            Pool::SimplePool(pool) => pool.share_register(account_id),
+            Pool::StableSwapPool(pool) => pool.share_register(account_id),
+            Pool::RatedSwapPool(pool) => pool.share_register(account_id),
+            Pool::WeightedPool(pool) => pool.share_register(account_id),
+        }
+    }
+
+    /// Returns true if given account has a shares entry, even with zero balance.
+    pub fn share_is_registered(&self, account_id: &AccountId) -> bool {
+        match self {
+            Pool::SimplePool(pool) => pool.share_is_registered(account_id),
+            Pool::StableSwapPool(pool) => pool.share_is_registered(account_id),
+            Pool::RatedSwapPool(pool) => pool.share_is_registered(account_id),
+            Pool::WeightedPool(pool) => pool.share_is_registered(account_id),
+        }
+    }
+
+    /// Removes shares entry of given account. Returns whether the account was registered.
+    pub fn share_unregister(&mut self, account_id: &AccountId) -> bool {
+        match self {
+            Pool::SimplePool(pool) => pool.share_unregister(account_id),
+            Pool::StableSwapPool(pool) => pool.share_unregister(account_id),
+            Pool::RatedSwapPool(pool) => pool.share_unregister(account_id),
+            Pool::WeightedPool(pool) => pool.share_unregister(account_id),
+        }
+    }
+
+    /// Transfers shares of this pool from sender to receiver.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        match self {
+            Pool::SimplePool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
+            Pool::StableSwapPool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
+            Pool::RatedSwapPool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
+            Pool::WeightedPool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
         }
     }
 
     pub fn add_liquidity(
-        &mut self, 
+        &mut self,
         sender_id: &AccountId,
-        amounts: &mut Vec<Balance>,
+        amounts: &mut [Balance],
+        admin_fee: &AdminFees,
     ) -> Balance {
         match self {
-            Pool::SimplePool(pool) => pool.add_liquidity(sender_id, amounts)
+            Pool::SimplePool(pool) => pool.add_liquidity(sender_id, amounts),
+            Pool::StableSwapPool(pool) => pool.add_liquidity(sender_id, amounts, admin_fee),
+            Pool::RatedSwapPool(pool) => pool.add_liquidity(sender_id, amounts, admin_fee),
+            Pool::WeightedPool(pool) => pool.add_liquidity(sender_id, amounts),
         }
     }
 
     pub fn tokens(&self) -> &[AccountId] {
         match self {
-            Pool::SimplePool(pool) => pool.tokens()
+            Pool::SimplePool(pool) => pool.tokens(),
+            Pool::StableSwapPool(pool) => pool.tokens(),
+            Pool::RatedSwapPool(pool) => pool.tokens(),
+            Pool::WeightedPool(pool) => pool.tokens(),
         }
     }
 
@@ -47,7 +120,72 @@ impl Pool {
         min_amounts: Vec<Balance>,
     ) -> Vec<Balance> {
         match self {
-            Pool::SimplePool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts)
+            Pool::SimplePool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts),
+            Pool::StableSwapPool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts),
+            Pool::RatedSwapPool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts),
+            Pool::WeightedPool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts),
+        }
+    }
+
+    /// Removes given amounts of tokens from the pool and returns number of shares burned.
+    /// Only supported by stable and rated swap pools.
+    pub fn remove_liquidity_by_tokens(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: Vec<Balance>,
+        max_burn_shares: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        match self {
+            Pool::StableSwapPool(pool) => {
+                pool.remove_liquidity_by_tokens(sender_id, amounts, max_burn_shares, admin_fee)
+            }
+            Pool::RatedSwapPool(pool) => {
+                pool.remove_liquidity_by_tokens(sender_id, amounts, max_burn_shares, admin_fee)
+            }
+            _ => env::panic(ERR58_NOT_STABLE_POOL.as_bytes()),
+        }
+    }
+
+    /// Returns how much token_out would be received for given amount of token_in.
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::RatedSwapPool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::WeightedPool(pool) => pool.get_return(token_in, amount_in, token_out),
+        }
+    }
+
+    /// Returns price of token_in in token_out excluding fees, multiplied by PRICE_PRECISION.
+    pub fn get_spot_price(&self, token_in: &AccountId, token_out: &AccountId) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.get_spot_price(token_in, token_out),
+            Pool::StableSwapPool(pool) => pool.get_spot_price(token_in, token_out),
+            Pool::RatedSwapPool(pool) => pool.get_spot_price(token_in, token_out),
+            Pool::WeightedPool(pool) => pool.get_spot_price(token_in, token_out),
+        }
+    }
+
+    /// Swaps given number of token_in for token_out and returns received amount.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.swap(token_in, amount_in, token_out, min_amount_out, admin_fee),
+            Pool::StableSwapPool(pool) => pool.swap(token_in, amount_in, token_out, min_amount_out, admin_fee),
+            Pool::RatedSwapPool(pool) => pool.swap(token_in, amount_in, token_out, min_amount_out, admin_fee),
+            Pool::WeightedPool(pool) => pool.swap(token_in, amount_in, token_out, min_amount_out, admin_fee),
         }
     }
 
@@ -55,20 +193,48 @@ impl Pool {
     pub fn get_volumes(&self) -> Vec<SwapVolume> {
         match self {
             Pool::SimplePool(pool) => pool.get_volumes(),
+            Pool::StableSwapPool(pool) => pool.get_volumes(),
+            Pool::RatedSwapPool(pool) => pool.get_volumes(),
+            Pool::WeightedPool(pool) => pool.get_volumes(),
+        }
+    }
+
+    /// Returns fees accumulated by the given pool, in the input token of each swap for simple
+    /// and weighted pools and in the output token for stable and rated swap pools.
+    pub fn get_fees(&self) -> Vec<Balance> {
+        match self {
+            Pool::SimplePool(pool) => pool.get_fees(),
+            Pool::StableSwapPool(pool) => pool.get_fees(),
+            Pool::RatedSwapPool(pool) => pool.get_fees(),
+            Pool::WeightedPool(pool) => pool.get_fees(),
         }
     }
 
     pub fn share_balances(&self, account_id: &AccountId) -> Balance {
         match self {
-            Pool::SimplePool(pool) => pool.share_balance_of(account_id)
+            Pool::SimplePool(pool) => pool.share_balance_of(account_id),
+            Pool::StableSwapPool(pool) => pool.share_balance_of(account_id),
+            Pool::RatedSwapPool(pool) => pool.share_balance_of(account_id),
+            Pool::WeightedPool(pool) => pool.share_balance_of(account_id),
+        }
+    }
+
+    /// Returns amounts of tokens in the pool, in the tokens' own decimals.
+    pub fn get_amounts(&self) -> Vec<Balance> {
+        match self {
+            Pool::SimplePool(pool) => pool.amounts.clone(),
+            Pool::StableSwapPool(pool) => pool.get_amounts(),
+            Pool::RatedSwapPool(pool) => pool.pool.get_amounts(),
+            Pool::WeightedPool(pool) => pool.amounts.clone(),
         }
     }
 
     pub fn share_total_balance(&self) -> Balance {
         match self {
-            Pool::SimplePool(pool) => pool.share_total_balance()
+            Pool::SimplePool(pool) => pool.share_total_balance(),
+            Pool::StableSwapPool(pool) => pool.share_total_balance(),
+            Pool::RatedSwapPool(pool) => pool.share_total_balance(),
+            Pool::WeightedPool(pool) => pool.share_total_balance(),
         }
     }
 }
-
-
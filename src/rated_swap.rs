@@ -0,0 +1,285 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, AccountId, Balance, Timestamp};
+
+use crate::admin_fee::AdminFees;
+use crate::errors::*;
+use crate::stable_swap::{StableSwapPool, RATE_DIVISOR};
+use crate::utils::SwapVolume;
+
+/// Cached rate older than this can not be used for pricing, in nanoseconds.
+pub const RATE_STALE_PERIOD: Timestamp = 3600 * 1_000_000_000;
+
+/// Rate of a token cached from its rate source.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct TokenRate {
+    /// Value of one token in the pool's unit of account, multiplied by RATE_DIVISOR.
+    pub value: Balance,
+    /// Block timestamp the rate was received at.
+    pub updated_at: Timestamp,
+}
+
+/// Stable swap pool, where balances of rated tokens are valued by the rate their contract reports,
+/// e.g. liquid staking token valued in the staked token.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct RatedSwapPool {
+    /// Stable swap pool the rates are applied to.
+    pub pool: StableSwapPool,
+    /// Cached rate of each token, `None` for tokens valued 1:1.
+    pub rates: Vec<Option<TokenRate>>,
+}
+
+impl RatedSwapPool {
+    pub fn new(
+        id: u32,
+        token_account_ids: Vec<ValidAccountId>,
+        token_decimals: Vec<u8>,
+        rated_tokens: Vec<ValidAccountId>,
+        amp_factor: u128,
+        total_fee: u32,
+    ) -> Self {
+        assert!(!rated_tokens.is_empty(), "{}", ERR64_NO_RATED_TOKENS);
+        for token_id in rated_tokens.iter() {
+            assert!(token_account_ids.contains(token_id), "{}", ERR41_MISSING_TOKEN);
+        }
+        // Rated tokens start without a rate, so the pool can not be used until rates are fetched.
+        let rates = token_account_ids
+            .iter()
+            .map(|token_id| {
+                if rated_tokens.contains(token_id) {
+                    Some(TokenRate {
+                        value: RATE_DIVISOR,
+                        updated_at: 0,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Self {
+            pool: StableSwapPool::new(id, token_account_ids, token_decimals, amp_factor, total_fee),
+            rates,
+        }
+    }
+
+    /// Returns index of given token if it is rated in this pool.
+    fn rated_token_index(&self, token_id: &AccountId) -> Option<usize> {
+        self.pool
+            .tokens()
+            .iter()
+            .position(|id| id == token_id)
+            .filter(|idx| self.rates[*idx].is_some())
+    }
+
+    /// Returns tokens rated in this pool.
+    pub fn rated_tokens(&self) -> impl Iterator<Item = &AccountId> {
+        self.pool
+            .tokens()
+            .iter()
+            .zip(self.rates.iter())
+            .filter(|(_, rate)| rate.is_some())
+            .map(|(token_id, _)| token_id)
+    }
+
+    /// Caches new rate of given token. Returns false if the token is not rated in this pool.
+    pub fn set_rate(&mut self, token_id: &AccountId, value: Balance) -> bool {
+        assert!(value > 0, "{}", ERR66_ZERO_RATE);
+        match self.rated_token_index(token_id) {
+            Some(idx) => {
+                self.rates[idx] = Some(TokenRate {
+                    value,
+                    updated_at: env::block_timestamp(),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns rates of all tokens in the pool, fails if any of the cached rates is stale.
+    pub fn current_rates(&self) -> Vec<Balance> {
+        let current_ts = env::block_timestamp();
+        self.rates
+            .iter()
+            .map(|rate| match rate {
+                Some(rate) => {
+                    assert!(
+                        rate.updated_at > 0 && current_ts <= rate.updated_at + RATE_STALE_PERIOD,
+                        "{}", ERR65_RATE_STALE
+                    );
+                    rate.value
+                }
+                None => RATE_DIVISOR,
+            })
+            .collect()
+    }
+
+    /// Register given account with 0 balance in shares.
+    pub fn share_register(&mut self, account_id: &AccountId) {
+        self.pool.share_register(account_id)
+    }
+
+    /// Returns true if given account has a shares entry, even with zero balance.
+    pub fn share_is_registered(&self, account_id: &AccountId) -> bool {
+        self.pool.share_is_registered(account_id)
+    }
+
+    /// Removes shares entry of given account, its shares should be transferred out before.
+    pub fn share_unregister(&mut self, account_id: &AccountId) -> bool {
+        self.pool.share_unregister(account_id)
+    }
+
+    /// Transfer shares from predecessor to receiver.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        self.pool.share_transfer(sender_id, receiver_id, amount)
+    }
+
+    /// Returns balance of shares for given user.
+    pub fn share_balance_of(&self, account_id: &AccountId) -> Balance {
+        self.pool.share_balance_of(account_id)
+    }
+
+    /// Returns total number of shares in this pool.
+    pub fn share_total_balance(&self) -> Balance {
+        self.pool.share_total_balance()
+    }
+
+    /// Returns list of tokens in this pool.
+    pub fn tokens(&self) -> &[AccountId] {
+        self.pool.tokens()
+    }
+
+    /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
+    pub fn add_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: &mut [Balance],
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let rates = self.current_rates();
+        self.pool.add_liquidity_with_rates(sender_id, amounts, admin_fee, &rates)
+    }
+
+    /// Removes given number of shares from the pool proportionally, does not depend on rates.
+    pub fn remove_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        min_amounts: Vec<Balance>,
+    ) -> Vec<Balance> {
+        self.pool.remove_liquidity(sender_id, shares, min_amounts)
+    }
+
+    /// Removes given amounts of tokens from the pool and returns number of shares burned.
+    pub fn remove_liquidity_by_tokens(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: Vec<Balance>,
+        max_burn_shares: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let rates = self.current_rates();
+        self.pool
+            .remove_liquidity_by_tokens_with_rates(sender_id, amounts, max_burn_shares, admin_fee, &rates)
+    }
+
+    /// Returns how much token you will receive if swap `token_amount_in` of `token_in` for `token_out`.
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.pool
+            .get_return_with_rates(token_in, amount_in, token_out, &self.current_rates())
+    }
+
+    /// Returns how much `token_out` one unit of `token_in` is worth at current reserves and rates,
+    /// excluding fees. Multiplied by PRICE_PRECISION.
+    pub fn get_spot_price(&self, token_in: &AccountId, token_out: &AccountId) -> Balance {
+        self.pool
+            .get_spot_price_with_rates(token_in, token_out, &self.current_rates())
+    }
+
+    /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let rates = self.current_rates();
+        self.pool
+            .swap_with_rates(token_in, amount_in, token_out, min_amount_out, admin_fee, &rates)
+    }
+
+    /// Returns volumes of the given pool.
+    pub fn get_volumes(&self) -> Vec<SwapVolume> {
+        self.pool.get_volumes()
+    }
+
+    /// Returns fees accumulated by the given pool, in the output token of each swap.
+    pub fn get_fees(&self) -> Vec<Balance> {
+        self.pool.get_fees()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    const START: Timestamp = 1_000_000_000_000_000_000;
+    const ONE_TOKEN: Balance = 1_000_000_000_000_000_000_000_000;
+
+    fn setup_pool(context: &mut VMContextBuilder) -> RatedSwapPool {
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .block_timestamp(START)
+            .build());
+        RatedSwapPool::new(0, vec![accounts(1), accounts(2)], vec![24, 24], vec![accounts(2)], 100, 5)
+    }
+
+    fn no_admin_fee() -> AdminFees {
+        AdminFees::new(0, 0, accounts(0).into(), None)
+    }
+
+    #[test]
+    fn test_set_rate() {
+        let mut context = VMContextBuilder::new();
+        let mut pool = setup_pool(&mut context);
+        assert_eq!(pool.rated_tokens().collect::<Vec<_>>(), vec![&AccountId::from(accounts(2))]);
+        assert!(!pool.set_rate(&accounts(1).into(), 2 * RATE_DIVISOR));
+        assert!(pool.set_rate(&accounts(2).into(), 2 * RATE_DIVISOR));
+        assert_eq!(pool.current_rates(), vec![RATE_DIVISOR, 2 * RATE_DIVISOR]);
+
+        testing_env!(context.block_timestamp(START + RATE_STALE_PERIOD).build());
+        assert_eq!(pool.current_rates(), vec![RATE_DIVISOR, 2 * RATE_DIVISOR]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E65: token rate is stale")]
+    fn test_rate_never_fetched() {
+        let mut context = VMContextBuilder::new();
+        let pool = setup_pool(&mut context);
+        pool.current_rates();
+    }
+
+    #[test]
+    #[should_panic(expected = "E65: token rate is stale")]
+    fn test_stale_rate_rejects_swap() {
+        let mut context = VMContextBuilder::new();
+        let mut pool = setup_pool(&mut context);
+        pool.set_rate(&accounts(2).into(), 2 * RATE_DIVISOR);
+        pool.share_register(&accounts(3).into());
+        pool.add_liquidity(&accounts(3).into(), &mut [200 * ONE_TOKEN, 100 * ONE_TOKEN], &no_admin_fee());
+        pool.get_return(&accounts(1).into(), ONE_TOKEN, &accounts(2).into());
+
+        testing_env!(context.block_timestamp(START + RATE_STALE_PERIOD + 1).build());
+        pool.swap(&accounts(1).into(), ONE_TOKEN, &accounts(2).into(), 0, &no_admin_fee());
+    }
+}
@@ -6,13 +6,11 @@ use near_sdk::json_types::ValidAccountId;
 use near_sdk::{env, AccountId, Balance};
 
 use crate::StorageKey;
-use crate::utils::{ SwapVolume, FEE_DIVISOR, U256, add_to_collection, integer_sqrt };
+use crate::admin_fee::AdminFees;
+use crate::errors::*;
+use crate::utils::{ SwapVolume, FEE_DIVISOR, MIN_LIQUIDITY, PRICE_PRECISION, U256, add_to_collection, integer_sqrt };
 
 const NUM_TOKENS: usize = 2;
-const ERR14_LP_ALREADY_REGISTERED: &str = "E14: LP already registered";
-const ERR13_LP_NOT_REGISTERED: &str = "E13: LP not registered";
-const ERR31_ZERO_AMOUNT: &str = "E31: adding zero amount";
-const ERR32_ZERO_SHARES: &str = "E32: minting zero shares";
 pub const INIT_SHARES_SUPPLY: u128 = 1_000_000_000_000_000_000_000_000;
 
 
@@ -25,6 +23,8 @@ pub struct SimplePool {
     pub amounts: Vec<Balance>,
     ///Volumes accumulated by this pool.
     pub volumes: Vec<SwapVolume>,
+    /// Fees accumulated by this pool, in the input token of each swap.
+    pub fees: Vec<Balance>,
     /// Fee charged for swap (gets divided by FEE_DEIVISOR).
     pub total_fee: u32,
     /// Obsolete, reserve to simplify upgrade.
@@ -35,6 +35,8 @@ pub struct SimplePool {
     pub shares: LookupMap<AccountId, Balance>,
     /// Total number of shares.
     pub shares_total_supply: Balance,
+    /// Shares locked forever by the first deposit, pools created before the lock have none.
+    pub locked_shares: Balance,
 }
 
 impl SimplePool {
@@ -47,15 +49,16 @@ impl SimplePool {
     ) -> Self {
        assert!(
            total_fee < FEE_DIVISOR,
-           "ERR_FEE_TOO_LARGE"
+           "{}", ERR50_FEE_TOO_LARGE
        );
 
        // [AUDIT_10]
-       assert_eq!(token_account_ids.len(), NUM_TOKENS, "ERR_SHOULD_HAVE_2_TOKENS");
+       assert_eq!(token_account_ids.len(), NUM_TOKENS, "{}", ERR51_SHOULD_HAVE_2_TOKENS);
        Self {
            token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
            amounts: vec![0u128; token_account_ids.len()],
            volumes: vec![SwapVolume::default(); token_account_ids.len()],
+           fees: vec![0u128; token_account_ids.len()],
            total_fee,
            exchange_fee,
            referral_fee,
@@ -64,6 +67,7 @@ impl SimplePool {
                pool_id: id,
            }),
            shares_total_supply: 0,
+           locked_shares: 0,
        }
     }
 
@@ -76,13 +80,24 @@ impl SimplePool {
         self.shares.insert(account_id, &0);
     }
 
+    /// Returns true if given account has a shares entry, even with zero balance.
+    pub fn share_is_registered(&self, account_id: &AccountId) -> bool {
+        self.shares.contains_key(account_id)
+    }
+
+    /// Removes shares entry of given account, its shares should be transferred out before.
+    /// Returns whether the account was registered.
+    pub fn share_unregister(&mut self, account_id: &AccountId) -> bool {
+        self.shares.remove(account_id).is_some()
+    }
+
     /// Transfer shares from predecessor to receiver.
     pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
-        let balance = self.shares.get(&sender_id).expect("ERR_NO_SHARES");
+        let balance = self.shares.get(&sender_id).expect(ERR33_NO_SHARES);
         if let Some(new_balance) = balance.checked_sub(amount) {
             self.shares.insert(&sender_id, &new_balance);
         } else {
-            env::panic(b"ERR_NOT_ENOUGH_SHARES")
+            env::panic(ERR34_NOT_ENOUGH_SHARES.as_bytes())
         }
         let balance_out = self
             .shares
@@ -107,16 +122,17 @@ impl SimplePool {
     }
 
     /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
-    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut Vec<Balance>) -> Balance {
+    #[allow(clippy::needless_range_loop)]
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut [Balance]) -> Balance {
         assert_eq!(
             amounts.len(),
             self.token_account_ids.len(),
-            "ERR_WRONG_TOKEN_COUNT"
+            "{}", ERR30_WRONG_TOKEN_COUNT
         );
         let shares = if self.shares_total_supply > 0 {
             let mut fair_supply = U256::max_value();
             for i in 0..self.token_account_ids.len() {
-                assert!(amounts[i] > 0, "{}", ERR31_ZERO_AMOUNT );
+                assert!(amounts[i] > 0, "{}", ERR31_ZERO_AMOUNT);
                 fair_supply = min(
                     fair_supply,
                     U256::from(amounts[i]) * U256::from(self.shares_total_supply) / self.amounts[i],
@@ -133,25 +149,15 @@ impl SimplePool {
             fair_supply.as_u128()
         } else {
             for i in 0..self.token_account_ids.len() {
+                assert!(amounts[i] > 0, "{}", ERR31_ZERO_AMOUNT);
                 self.amounts[i] += amounts[i];
             }
-            INIT_SHARES_SUPPLY
-            
+            self.mint_shares(&env::current_account_id(), MIN_LIQUIDITY);
+            self.locked_shares = MIN_LIQUIDITY;
+            INIT_SHARES_SUPPLY - MIN_LIQUIDITY
         };
         self.mint_shares(&sender_id, shares);
         assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
-        env::log(
-            format!(
-                "liquidity added {:?}, minted {} shares",
-                amounts
-                    .iter()
-                    .zip(self.token_account_ids.iter())
-                    .map(|(amount, token_id)| format!("{} {}", amount, token_id))
-                    .collect::<Vec<String>>(),
-                shares
-            )
-            .as_bytes(),
-        );
         shares
     }
 
@@ -165,6 +171,7 @@ impl SimplePool {
     }
 
     /// Removes given number of shares form the pool and returns amounts to the parent.
+    #[allow(clippy::needless_range_loop)]
     pub fn remove_liquidity(
         &mut self, 
         sender_id: &AccountId,
@@ -174,16 +181,20 @@ impl SimplePool {
         assert_eq!(
             min_amounts.len(),
             self.token_account_ids.len(),
-            "ERR_WRONG_TOKEN_COUNT"
+            "{}", ERR30_WRONG_TOKEN_COUNT
+        );
+        let prev_shares_amount = self.shares.get(&sender_id).expect(ERR33_NO_SHARES);
+        assert!(prev_shares_amount >= shares, "{}", ERR34_NOT_ENOUGH_SHARES);
+        assert!(
+            self.shares_total_supply - shares >= self.locked_shares,
+            "{}", ERR37_MIN_LIQUIDITY
         );
-        let prev_shares_amount = self.shares.get(&sender_id).expect("ERR_NO_SHARES");
-        assert!(prev_shares_amount >= shares, "ERR_NOT_ENOUGH_SHARES");
         let mut result = vec![];
         for i in 0..self.token_account_ids.len() {
             let amount = (U256::from(self.amounts[i]) * U256::from(shares)
                 / U256::from(self.shares_total_supply))
             .as_u128();
-            assert!(amount >= min_amounts[i], "ERR_MIN_AMOUNT");
+            assert!(amount >= min_amounts[i], "{}", ERR35_MIN_AMOUNT);
             self.amounts[i] -= amount;
             result.push(amount);
         }
@@ -193,18 +204,6 @@ impl SimplePool {
             self.shares
                 .insert(&sender_id, &(prev_shares_amount - shares));
         }
-        env::log(
-            format!(
-                "{} shares of liquidity removed: receive back {:?}", 
-                shares,
-                result
-                    .iter()
-                    .zip(self.token_account_ids.iter())
-                    .map(|(amount, token_id)| format!("{} {}", amount, token_id))
-                    .collect::<Vec<String>>(),
-            )
-            .as_bytes(),
-        );
         self.shares_total_supply -= shares;
         result
     }
@@ -214,7 +213,7 @@ impl SimplePool {
         self.token_account_ids
             .iter()
             .position(|id| id == token_id)
-            .expect("ERR_MISSING_TOKEN")
+            .expect(ERR41_MISSING_TOKEN)
     }
 
     /// Returns number of tokens in outcome, given amount.
@@ -232,7 +231,7 @@ impl SimplePool {
                 && out_balance > U256::zero()
                 && token_in != token_out
                 && amount_in > 0,
-            "ERR_INVALID"
+            "{}", ERR48_INVALID_SWAP
         );
         let amount_with_fee = U256::from(amount_in) * U256::from(FEE_DIVISOR - self.total_fee);
         (amount_with_fee * out_balance / (U256::from(FEE_DIVISOR) * in_balance + amount_with_fee))
@@ -253,6 +252,77 @@ impl SimplePool {
         )
     }
 
+    /// Returns how much `token_out` one unit of `token_in` is worth at current reserves, excluding fees.
+    /// Multiplied by PRICE_PRECISION.
+    pub fn get_spot_price(&self, token_in: &AccountId, token_out: &AccountId) -> Balance {
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        assert!(
+            self.amounts[in_idx] > 0 && self.amounts[out_idx] > 0 && in_idx != out_idx,
+            "{}", ERR48_INVALID_SWAP
+        );
+        (U256::from(self.amounts[out_idx]) * U256::from(PRICE_PRECISION)
+            / U256::from(self.amounts[in_idx]))
+        .as_u128()
+    }
+
+    /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
+    /// Assuming that `token_amount_in` was already received from `sender_id`.
+    /// Exchange and referral parts of the fee are minted to them as LP shares.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let amount_out = self.internal_get_return(in_idx, amount_in, out_idx);
+        assert!(amount_out >= min_amount_out, "{}", ERR35_MIN_AMOUNT);
+
+        let prev_invariant =
+            integer_sqrt(U256::from(self.amounts[in_idx]) * U256::from(self.amounts[out_idx]));
+
+        self.amounts[in_idx] += amount_in;
+        self.amounts[out_idx] -= amount_out;
+
+        // "Invariant" is by how much the dot product of amounts increased due to fees.
+        let new_invariant =
+            integer_sqrt(U256::from(self.amounts[in_idx]) * U256::from(self.amounts[out_idx]));
+
+        // Invariant can not reduce (otherwise loosing balance of the pool and something it broken).
+        assert!(new_invariant >= prev_invariant, "{}", ERR46_INVARIANT);
+        let numerator = (new_invariant - prev_invariant) * U256::from(self.shares_total_supply);
+
+        // Allocate exchange fee as fraction of total fee by issuing LP shares proportionally.
+        if admin_fee.exchange_fee > 0 && numerator > U256::zero() {
+            let denominator = new_invariant * FEE_DIVISOR / admin_fee.exchange_fee;
+            self.mint_shares(&admin_fee.exchange_id, (numerator / denominator).as_u128());
+        }
+
+        // If there is referral provided and the account already registered LP, allocate it % of LP rewards.
+        if let Some(referral_id) = &admin_fee.referral_id {
+            if admin_fee.referral_fee > 0
+                && numerator > U256::zero()
+                && self.shares.contains_key(referral_id)
+            {
+                let denominator = new_invariant * FEE_DIVISOR / admin_fee.referral_fee;
+                self.mint_shares(referral_id, (numerator / denominator).as_u128());
+            }
+        }
+
+        // Keeping track of volume per each input traded separately.
+        // Reported volume with fees will be sum of `input`, without fees will be sum of `output`.
+        self.volumes[in_idx].input.0 += amount_in;
+        self.volumes[out_idx].output.0 += amount_out;
+        self.fees[in_idx] +=
+            (U256::from(amount_in) * U256::from(self.total_fee) / U256::from(FEE_DIVISOR)).as_u128();
+
+        amount_out
+    }
+
     /// Returns given pool's total fee.
     pub fn get_fee(&self)-> u32 {
         self.total_fee
@@ -262,4 +332,77 @@ impl SimplePool {
     pub fn get_volumes(&self) -> Vec<SwapVolume> {
         self.volumes.clone()
     }
+
+    /// Returns fees accumulated by the given pool.
+    pub fn get_fees(&self) -> Vec<Balance> {
+        self.fees.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    fn setup_pool(total_fee: u32) -> SimplePool {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.current_account_id(accounts(0)).build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], total_fee, 0, 0);
+        pool.share_register(&accounts(0).into());
+        pool.share_register(&accounts(3).into());
+        pool
+    }
+
+    #[test]
+    fn test_add_remove_liquidity() {
+        let mut pool = setup_pool(30);
+        let user: AccountId = accounts(3).into();
+        let shares = pool.add_liquidity(&user, &mut [100_000, 200_000]);
+        assert_eq!(shares, INIT_SHARES_SUPPLY - MIN_LIQUIDITY);
+        assert_eq!(pool.share_balance_of(&accounts(0).into()), MIN_LIQUIDITY);
+        let mut amounts = vec![50_000, 100_000];
+        assert_eq!(pool.add_liquidity(&user, &mut amounts), INIT_SHARES_SUPPLY / 2);
+        assert_eq!(amounts, vec![50_000, 100_000]);
+
+        let amounts = pool.remove_liquidity(&user, pool.share_balance_of(&user), vec![0, 0]);
+        assert_eq!(amounts, vec![149_999, 299_999]);
+        assert_eq!(pool.share_total_balance(), MIN_LIQUIDITY);
+    }
+
+    #[test]
+    fn test_swap_mints_fee_shares() {
+        let mut pool = setup_pool(30);
+        let user: AccountId = accounts(3).into();
+        pool.add_liquidity(&user, &mut [100 * INIT_SHARES_SUPPLY, 100 * INIT_SHARES_SUPPLY]);
+        let exchange_id: AccountId = accounts(0).into();
+        let exchange_shares = pool.share_balance_of(&exchange_id);
+        let total_shares = pool.share_total_balance();
+        let admin_fee = AdminFees::new(1600, 400, exchange_id.clone(), Some(user.clone()));
+        let amount_in = INIT_SHARES_SUPPLY;
+        pool.swap(&accounts(1).into(), amount_in, &accounts(2).into(), 0, &admin_fee);
+
+        assert_eq!(pool.get_fees(), vec![amount_in * 30 / 10_000, 0]);
+        let exchange_minted = pool.share_balance_of(&exchange_id) - exchange_shares;
+        let referral_minted = pool.share_total_balance() - total_shares - exchange_minted;
+        assert!(exchange_minted > 0);
+        // Referral gets 400 and exchange 1600 bps of the fee growth.
+        assert!(exchange_minted.max(4 * referral_minted) - exchange_minted.min(4 * referral_minted) <= 4);
+
+        // Referral without shares entry in the pool gets nothing.
+        let total_shares = pool.share_total_balance();
+        let admin_fee = AdminFees::new(1600, 400, exchange_id, Some(accounts(4).into()));
+        pool.swap(&accounts(1).into(), amount_in, &accounts(2).into(), 0, &admin_fee);
+        assert_eq!(pool.share_balance_of(&accounts(4).into()), 0);
+        assert!(pool.share_total_balance() > total_shares);
+    }
+
+    /// Seeding a pool with a zero amount made every later deposit and withdrawal divide by zero.
+    #[test]
+    #[should_panic(expected = "E31: adding zero amount")]
+    fn test_first_deposit_zero_amount() {
+        let mut pool = setup_pool(30);
+        pool.add_liquidity(&accounts(3).into(), &mut [0, 10]);
+    }
 }
@@ -0,0 +1,403 @@
+use near_sdk::{Balance, Timestamp};
+
+use crate::utils::{FEE_DIVISOR, PRICE_PRECISION, U256};
+
+/// Min amplification coefficient.
+pub const MIN_AMP: u128 = 1;
+/// Max amplification coefficient.
+pub const MAX_AMP: u128 = 1_000_000;
+/// Max amplification change in a single ramp, up or down.
+pub const MAX_AMP_CHANGE: u128 = 10;
+/// Minimum ramp duration, in nanoseconds.
+pub const MIN_RAMP_DURATION: Timestamp = 86_400 * 1_000_000_000;
+
+/// Swap result in comparable amounts.
+pub struct SwapResult {
+    /// Amount of destination token swapped.
+    pub amount_swapped: Balance,
+    /// Fee charged for trade, in destination token.
+    pub fee: Balance,
+}
+
+/// Fees charged by the pool.
+pub struct Fees {
+    /// Fee charged for trade (gets divided by FEE_DIVISOR).
+    pub trade_fee: u32,
+}
+
+impl Fees {
+    pub fn new(total_fee: u32) -> Self {
+        Self {
+            trade_fee: total_fee,
+        }
+    }
+
+    pub fn zero() -> Self {
+        Self { trade_fee: 0 }
+    }
+
+    pub fn trade_fee(&self, amount: Balance) -> Balance {
+        (U256::from(amount) * U256::from(self.trade_fee) / U256::from(FEE_DIVISOR)).as_u128()
+    }
+
+    /// Fee applied on the difference between actual and ideal balance after liquidity change.
+    /// Normalized so that the full imbalance of n coins costs the same as one trade.
+    pub fn normalized_trade_fee(&self, num_coins: u32, amount: Balance) -> Balance {
+        let adjusted_trade_fee = (self.trade_fee * num_coins) / (4 * (num_coins - 1));
+        (U256::from(amount) * U256::from(adjusted_trade_fee) / U256::from(FEE_DIVISOR)).as_u128()
+    }
+}
+
+/// Curve-style stable swap invariant with given amplification coefficient.
+/// All amounts are comparable amounts (normalized to the same decimals).
+pub struct StableSwap {
+    amp_factor: u128,
+}
+
+impl StableSwap {
+    pub fn new(amp_factor: u128) -> Self {
+        Self { amp_factor }
+    }
+
+    /// Compute stable swap invariant (D)
+    /// Equation:
+    /// A * sum(x_i) * n**n + D = A * D * n**n + D**(n+1) / (n**n * prod(x_i))
+    pub fn compute_d(&self, c_amounts: &[Balance]) -> Option<U256> {
+        let n_coins = c_amounts.len() as u128;
+        let sum_x = c_amounts
+            .iter()
+            .try_fold(0u128, |sum, amount| sum.checked_add(*amount))?;
+        if sum_x == 0 {
+            return Some(U256::zero());
+        }
+        let ann = U256::from(self.amp_factor.checked_mul(n_coins.checked_pow(n_coins as u32)?)?);
+        let leverage = U256::from(sum_x).checked_mul(ann)?;
+        let mut d = U256::from(sum_x);
+        for _ in 0..256 {
+            // D_prod = D**(n+1) / (n**n * prod(x_i)), +1 prevents dividing by zero.
+            let mut d_prod = d;
+            for c_amount in c_amounts {
+                d_prod = d_prod
+                    .checked_mul(d)?
+                    .checked_div(U256::from(*c_amount) * U256::from(n_coins) + 1)?;
+            }
+            let d_prev = d;
+            // d = (ann * sum_x + d_prod * n) * d_prev / ((ann - 1) * d_prev + (n + 1) * d_prod)
+            let numerator = d_prev.checked_mul(
+                d_prod
+                    .checked_mul(U256::from(n_coins))?
+                    .checked_add(leverage)?,
+            )?;
+            let denominator = d_prev
+                .checked_mul(ann.checked_sub(U256::one())?)?
+                .checked_add(d_prod.checked_mul(U256::from(n_coins + 1))?)?;
+            d = numerator.checked_div(denominator)?;
+            // Equality with the precision of 1.
+            if abs_diff(d, d_prev) <= U256::one() {
+                break;
+            }
+        }
+        Some(d)
+    }
+
+    /// Compute new amount of token 'y' with new amount of token 'x'
+    /// return new y_token amount according to the equation.
+    pub fn compute_y(
+        &self,
+        x_c_amount: Balance,
+        current_c_amounts: &[Balance],
+        index_x: usize,
+        index_y: usize,
+    ) -> Option<U256> {
+        let n_coins = current_c_amounts.len() as u128;
+        let n_pow_n = n_coins.checked_pow(n_coins as u32)?;
+        let ann = U256::from(self.amp_factor.checked_mul(n_pow_n)?);
+        let d = self.compute_d(current_c_amounts)?;
+
+        let mut s = U256::from(x_c_amount);
+        let mut c = d.checked_mul(d)?.checked_div(U256::from(x_c_amount))?;
+        for (idx, c_amount) in current_c_amounts.iter().enumerate() {
+            if idx != index_x && idx != index_y {
+                s = s.checked_add(U256::from(*c_amount))?;
+                c = c.checked_mul(d)?.checked_div(U256::from(*c_amount))?;
+            }
+        }
+        c = c
+            .checked_mul(d)?
+            .checked_div(ann.checked_mul(U256::from(n_pow_n))?)?;
+        // d will be subtracted later.
+        let b = d.checked_div(ann)?.checked_add(s)?;
+
+        // Solve for y by approximating: y**2 + b*y = c
+        let mut y = d;
+        for _ in 0..256 {
+            let y_prev = y;
+            // y = (y**2 + c) / (2y + b - d)
+            let numerator = y.checked_mul(y)?.checked_add(c)?;
+            let denominator = y
+                .checked_mul(U256::from(2))?
+                .checked_add(b)?
+                .checked_sub(d)?;
+            y = numerator.checked_div(denominator)?;
+            if abs_diff(y, y_prev) <= U256::one() {
+                break;
+            }
+        }
+        Some(y)
+    }
+
+    /// Compute marginal price of token 'x' in token 'y' at current amounts, excluding fees,
+    /// multiplied by PRICE_PRECISION.
+    /// Equation, with D_P = D**(n+1) / (n**n * prod(x_i)):
+    /// -dy/dx = y * (A * n**n * x + D_P) / (x * (A * n**n * y + D_P))
+    pub fn compute_spot_price(
+        &self,
+        current_c_amounts: &[Balance],
+        index_x: usize,
+        index_y: usize,
+    ) -> Option<U256> {
+        let n_coins = current_c_amounts.len() as u128;
+        let ann = U256::from(self.amp_factor.checked_mul(n_coins.checked_pow(n_coins as u32)?)?);
+        let d = self.compute_d(current_c_amounts)?;
+        let mut d_p = d;
+        for c_amount in current_c_amounts {
+            d_p = d_p
+                .checked_mul(d)?
+                .checked_div(U256::from(*c_amount).checked_mul(U256::from(n_coins))?)?;
+        }
+        let x = U256::from(current_c_amounts[index_x]);
+        let y = U256::from(current_c_amounts[index_y]);
+        y.checked_mul(U256::from(PRICE_PRECISION))?
+            .checked_div(x)?
+            .checked_mul(ann.checked_mul(x)?.checked_add(d_p)?)?
+            .checked_div(ann.checked_mul(y)?.checked_add(d_p)?)
+    }
+
+    /// Compute the amount of LP tokens to mint after a deposit.
+    /// Returns <lp_amount_to_mint, lp_fees_part>.
+    pub fn compute_lp_amount_for_deposit(
+        &self,
+        deposit_c_amounts: &[Balance],
+        old_c_amounts: &[Balance],
+        pool_token_supply: Balance,
+        fees: &Fees,
+    ) -> Option<(Balance, Balance)> {
+        let n_coins = old_c_amounts.len();
+        // Initial invariant.
+        let d_0 = self.compute_d(old_c_amounts)?;
+        let mut new_balances = vec![0u128; n_coins];
+        for (index, value) in deposit_c_amounts.iter().enumerate() {
+            new_balances[index] = old_c_amounts[index].checked_add(*value)?;
+        }
+        // Invariant after change.
+        let d_1 = self.compute_d(&new_balances)?;
+        if d_1 <= d_0 {
+            return None;
+        }
+        // Recalculate the invariant accounting for fees.
+        for (new_balance, old_balance) in new_balances.iter_mut().zip(old_c_amounts.iter()) {
+            let ideal_balance = d_1
+                .checked_mul(U256::from(*old_balance))?
+                .checked_div(d_0)?
+                .as_u128();
+            let difference = ideal_balance.max(*new_balance) - ideal_balance.min(*new_balance);
+            let fee = fees.normalized_trade_fee(n_coins as u32, difference);
+            *new_balance = new_balance.checked_sub(fee)?;
+        }
+        let d_2 = self.compute_d(&new_balances)?;
+        // d1 > d2 > d0, (d2-d0) => mint_shares (fee charged), (d1-d0) => diff_shares (without fee).
+        let mint_shares = U256::from(pool_token_supply)
+            .checked_mul(d_2.checked_sub(d_0)?)?
+            .checked_div(d_0)?
+            .as_u128();
+        let diff_shares = U256::from(pool_token_supply)
+            .checked_mul(d_1.checked_sub(d_0)?)?
+            .checked_div(d_0)?
+            .as_u128();
+        Some((mint_shares, diff_shares - mint_shares))
+    }
+
+    /// Compute the amount of LP tokens to burn for given withdrawal.
+    /// Returns <lp_amount_to_burn, lp_fees_part>.
+    pub fn compute_lp_amount_for_withdraw(
+        &self,
+        withdraw_c_amounts: &[Balance],
+        old_c_amounts: &[Balance],
+        pool_token_supply: Balance,
+        fees: &Fees,
+    ) -> Option<(Balance, Balance)> {
+        let n_coins = old_c_amounts.len();
+        // Initial invariant.
+        let d_0 = self.compute_d(old_c_amounts)?;
+        let mut new_balances = vec![0u128; n_coins];
+        for (index, value) in withdraw_c_amounts.iter().enumerate() {
+            new_balances[index] = old_c_amounts[index].checked_sub(*value)?;
+        }
+        // Invariant after change.
+        let d_1 = self.compute_d(&new_balances)?;
+        // Recalculate the invariant accounting for fees.
+        for (new_balance, old_balance) in new_balances.iter_mut().zip(old_c_amounts.iter()) {
+            let ideal_balance = d_1
+                .checked_mul(U256::from(*old_balance))?
+                .checked_div(d_0)?
+                .as_u128();
+            let difference = ideal_balance.max(*new_balance) - ideal_balance.min(*new_balance);
+            let fee = fees.normalized_trade_fee(n_coins as u32, difference);
+            *new_balance = new_balance.checked_sub(fee)?;
+        }
+        let d_2 = self.compute_d(&new_balances)?;
+        // d0 > d1 > d2, (d0-d2) => burn_shares (fee charged), (d0-d1) => diff_shares (without fee).
+        let burn_shares = U256::from(pool_token_supply)
+            .checked_mul(d_0.checked_sub(d_2)?)?
+            .checked_div(d_0)?
+            .as_u128();
+        let diff_shares = U256::from(pool_token_supply)
+            .checked_mul(d_0.checked_sub(d_1)?)?
+            .checked_div(d_0)?
+            .as_u128();
+        Some((burn_shares, burn_shares - diff_shares))
+    }
+
+    /// Compute SwapResult after an exchange.
+    pub fn swap_to(
+        &self,
+        token_in_idx: usize,
+        token_in_amount: Balance,
+        token_out_idx: usize,
+        current_c_amounts: &[Balance],
+        fees: &Fees,
+    ) -> Option<SwapResult> {
+        let new_source_amount = current_c_amounts[token_in_idx].checked_add(token_in_amount)?;
+        let y = self
+            .compute_y(new_source_amount, current_c_amounts, token_in_idx, token_out_idx)?
+            .as_u128();
+        let dy = current_c_amounts[token_out_idx].checked_sub(y)?;
+        let fee = fees.trade_fee(dy);
+        let amount_swapped = dy.checked_sub(fee)?;
+        Some(SwapResult {
+            amount_swapped,
+            fee,
+        })
+    }
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: Balance = 1_000_000_000_000_000_000;
+
+    /// Asserts that values differ by at most given tolerance.
+    fn assert_close(actual: u128, expected: u128, tolerance: u128) {
+        let diff = actual.max(expected) - actual.min(expected);
+        assert!(diff <= tolerance, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_compute_d() {
+        let swap = StableSwap::new(100);
+        assert_eq!(swap.compute_d(&[0, 0]).unwrap(), U256::zero());
+        assert_close(swap.compute_d(&[1000 * ONE, 1000 * ONE]).unwrap().as_u128(), 2000 * ONE, 1);
+        // Reference values solve the invariant with 80 digits precision.
+        assert_close(
+            swap.compute_d(&[1000 * ONE, 2000 * ONE]).unwrap().as_u128(),
+            2_999_068_031_122_620_559_438,
+            2,
+        );
+        assert_close(
+            StableSwap::new(10)
+                .compute_d(&[100 * ONE, 300 * ONE, 50 * ONE])
+                .unwrap()
+                .as_u128(),
+            447_976_178_029_990_377_420,
+            2,
+        );
+    }
+
+    #[test]
+    fn test_compute_y() {
+        let swap = StableSwap::new(100);
+        let amounts = [1000 * ONE, 1000 * ONE];
+        assert_close(swap.compute_y(1000 * ONE, &amounts, 0, 1).unwrap().as_u128(), 1000 * ONE, 1);
+        assert_close(
+            swap.compute_y(1100 * ONE, &amounts, 0, 1).unwrap().as_u128(),
+            900_050_223_229_924_554_033,
+            2,
+        );
+        assert_close(
+            StableSwap::new(10)
+                .compute_y(1500 * ONE, &[1000 * ONE, 2000 * ONE], 0, 1)
+                .unwrap()
+                .as_u128(),
+            1_491_148_987_990_837_410_748,
+            2,
+        );
+    }
+
+    #[test]
+    fn test_swap_to() {
+        let swap = StableSwap::new(100);
+        let amounts = [1000 * ONE, 1000 * ONE];
+        let result = swap.swap_to(0, 100 * ONE, 1, &amounts, &Fees::zero()).unwrap();
+        assert_close(result.amount_swapped, 99_949_776_770_075_445_966, 2);
+        assert_eq!(result.fee, 0);
+
+        let result = swap.swap_to(0, 100 * ONE, 1, &amounts, &Fees::new(30)).unwrap();
+        let dy = result.amount_swapped + result.fee;
+        assert_close(dy, 99_949_776_770_075_445_966, 2);
+        assert_eq!(result.fee, dy * 30 / 10_000);
+    }
+
+    #[test]
+    fn test_lp_amount_balanced_round_trip() {
+        let swap = StableSwap::new(100);
+        let amounts = [1000 * ONE, 2000 * ONE];
+        let supply = 3000 * ONE;
+        let deposit = [100 * ONE, 200 * ONE];
+        // Proportional deposit grows D by 10% and is not charged.
+        let (minted, fee_part) = swap
+            .compute_lp_amount_for_deposit(&deposit, &amounts, supply, &Fees::new(30))
+            .unwrap();
+        assert_close(minted, supply / 10, 10);
+        assert!(fee_part <= 1);
+
+        let new_amounts = [1100 * ONE, 2200 * ONE];
+        let (burned, fee_part) = swap
+            .compute_lp_amount_for_withdraw(&deposit, &new_amounts, supply + minted, &Fees::new(30))
+            .unwrap();
+        assert_close(burned, minted, 10);
+        assert!(fee_part <= 1);
+    }
+
+    #[test]
+    fn test_lp_amount_imbalanced_fee() {
+        let swap = StableSwap::new(100);
+        let amounts = [1000 * ONE, 1000 * ONE];
+        let supply = 2000 * ONE;
+        let (no_fee_minted, no_fee_part) = swap
+            .compute_lp_amount_for_deposit(&[100 * ONE, 0], &amounts, supply, &Fees::zero())
+            .unwrap();
+        assert_eq!(no_fee_part, 0);
+        let (minted, fee_part) = swap
+            .compute_lp_amount_for_deposit(&[100 * ONE, 0], &amounts, supply, &Fees::new(30))
+            .unwrap();
+        assert!(fee_part > 0);
+        assert_eq!(minted + fee_part, no_fee_minted);
+
+        // Withdrawing the same imbalanced amount burns more shares than the deposit minted.
+        let new_amounts = [1100 * ONE, 1000 * ONE];
+        let (burned, fee_part) = swap
+            .compute_lp_amount_for_withdraw(&[100 * ONE, 0], &new_amounts, supply + minted, &Fees::new(30))
+            .unwrap();
+        assert!(fee_part > 0);
+        assert!(burned > minted);
+    }
+}
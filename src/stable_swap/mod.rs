@@ -3,6 +3,25 @@ use near_sdk::collections::LookupMap;
 use near_sdk::json_types::ValidAccountId;
 use near_sdk::{env, AccountId, Balance, Timestamp};
 
+use crate::admin_fee::AdminFees;
+use crate::errors::*;
+use crate::utils::{add_to_collection, SwapVolume, FEE_DIVISOR, MIN_LIQUIDITY, U256};
+use crate::StorageKey;
+
+pub use self::math::{MAX_AMP, MAX_AMP_CHANGE, MIN_AMP, MIN_RAMP_DURATION};
+use self::math::{Fees, StableSwap, SwapResult};
+
+mod math;
+
+/// Decimals all token amounts are normalized to inside the pool.
+pub const TARGET_DECIMAL: u8 = 24;
+/// Minimum number of tokens in the pool.
+pub const MIN_NUM_TOKENS: usize = 2;
+/// Maximum number of tokens in the pool.
+pub const MAX_NUM_TOKENS: usize = 9;
+/// Token rates are given as multiple of this value, plain stable pools use it for every token.
+pub const RATE_DIVISOR: Balance = 1_000_000_000_000_000_000_000_000;
+
 
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct StableSwapPool {
@@ -14,12 +33,16 @@ pub struct StableSwapPool {
     pub c_amounts: Vec<Balance>,
     /// Volumes accumulated by this pool.
     pub volumes: Vec<SwapVolume>,
+    /// Fees accumulated by this pool, in the output token of each swap.
+    pub fees: Vec<Balance>,
     /// Fee charged for swap (gets divided by FEE_DIVISOR)
     pub total_fee: u32,
     /// Shares of the pool by liquidity providers.
-    pub shares: LoopupMap<AccountId, Balance>,
+    pub shares: LookupMap<AccountId, Balance>,
     /// Total number of shares.
     pub shares_total_supply: Balance,
+    /// Shares locked forever by the first deposit, pools created before the lock have none.
+    pub locked_shares: Balance,
     /// Initial amplification coefficient.
     pub init_amp_factor: u128,
     /// Target for ramping up amplification coefficient.
@@ -28,4 +51,611 @@ pub struct StableSwapPool {
     pub init_amp_time: Timestamp,
     /// Stop ramp up amplification time.
     pub stop_amp_time: Timestamp,
-}
\ No newline at end of file
+}
+
+impl StableSwapPool {
+    pub fn new(
+        id: u32,
+        token_account_ids: Vec<ValidAccountId>,
+        token_decimals: Vec<u8>,
+        amp_factor: u128,
+        total_fee: u32,
+    ) -> Self {
+        assert!((MIN_AMP..=MAX_AMP).contains(&amp_factor), "{}", ERR54_INVALID_AMP);
+        assert!(total_fee < FEE_DIVISOR, "{}", ERR50_FEE_TOO_LARGE);
+        assert!(
+            (MIN_NUM_TOKENS..=MAX_NUM_TOKENS).contains(&token_account_ids.len()),
+            "{}", ERR30_WRONG_TOKEN_COUNT
+        );
+        assert_eq!(
+            token_decimals.len(),
+            token_account_ids.len(),
+            "{}", ERR52_WRONG_DECIMALS_COUNT
+        );
+        for decimals in token_decimals.iter() {
+            assert!(*decimals <= TARGET_DECIMAL, "{}", ERR53_DECIMALS_TOO_LARGE);
+        }
+        Self {
+            token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
+            token_decimals,
+            c_amounts: vec![0u128; token_account_ids.len()],
+            volumes: vec![SwapVolume::default(); token_account_ids.len()],
+            fees: vec![0u128; token_account_ids.len()],
+            total_fee,
+            shares: LookupMap::new(StorageKey::Shares { pool_id: id }),
+            shares_total_supply: 0,
+            locked_shares: 0,
+            init_amp_factor: amp_factor,
+            target_amp_factor: amp_factor,
+            init_amp_time: 0,
+            stop_amp_time: 0,
+        }
+    }
+
+    /// Register given account with 0 balance in shares.
+    /// Storage payment should be checked by caller.
+    pub fn share_register(&mut self, account_id: &AccountId) {
+        if self.shares.contains_key(account_id) {
+            env::panic(ERR14_LP_ALREADY_REGISTERED.as_bytes());
+        }
+        self.shares.insert(account_id, &0);
+    }
+
+    /// Returns true if given account has a shares entry, even with zero balance.
+    pub fn share_is_registered(&self, account_id: &AccountId) -> bool {
+        self.shares.contains_key(account_id)
+    }
+
+    /// Removes shares entry of given account, its shares should be transferred out before.
+    /// Returns whether the account was registered.
+    pub fn share_unregister(&mut self, account_id: &AccountId) -> bool {
+        self.shares.remove(account_id).is_some()
+    }
+
+    /// Transfer shares from predecessor to receiver.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        let balance = self.shares.get(sender_id).expect(ERR33_NO_SHARES);
+        if let Some(new_balance) = balance.checked_sub(amount) {
+            self.shares.insert(sender_id, &new_balance);
+        } else {
+            env::panic(ERR34_NOT_ENOUGH_SHARES.as_bytes())
+        }
+        let balance_out = self
+            .shares
+            .get(receiver_id)
+            .expect(ERR13_LP_NOT_REGISTERED);
+        self.shares.insert(receiver_id, &(balance_out + amount));
+    }
+
+    /// Returns balance of shares for given user.
+    pub fn share_balance_of(&self, account_id: &AccountId) -> Balance {
+        self.shares.get(account_id).unwrap_or_default()
+    }
+
+    /// Returns total number of shares in this pool.
+    pub fn share_total_balance(&self) -> Balance {
+        self.shares_total_supply
+    }
+
+    /// Returns list of tokens in this pool.
+    pub fn tokens(&self) -> &[AccountId] {
+        &self.token_account_ids
+    }
+
+    /// Returns token amounts of the pool in their own decimals.
+    pub fn get_amounts(&self) -> Vec<Balance> {
+        self.c_amounts
+            .iter()
+            .enumerate()
+            .map(|(idx, c_amount)| c_amount / self.decimal_factor(idx))
+            .collect()
+    }
+
+    /// Returns amplification coefficient currently used by the invariant.
+    /// While ramping it moves linearly from `init_amp_factor` to `target_amp_factor`.
+    pub fn get_amp(&self) -> u128 {
+        let current_ts = env::block_timestamp();
+        if current_ts >= self.stop_amp_time {
+            return self.target_amp_factor;
+        }
+        let time_range = (self.stop_amp_time - self.init_amp_time) as u128;
+        let time_delta = (current_ts.saturating_sub(self.init_amp_time)) as u128;
+        if self.target_amp_factor >= self.init_amp_factor {
+            let amp_range = self.target_amp_factor - self.init_amp_factor;
+            self.init_amp_factor + amp_range * time_delta / time_range
+        } else {
+            let amp_range = self.init_amp_factor - self.target_amp_factor;
+            self.init_amp_factor - amp_range * time_delta / time_range
+        }
+    }
+
+    /// Starts ramping amplification coefficient from current value to `future_amp_factor`,
+    /// reached at `future_amp_time`.
+    pub fn ramp_amplification(&mut self, future_amp_factor: u128, future_amp_time: Timestamp) {
+        let current_ts = env::block_timestamp();
+        assert!(
+            current_ts >= self.init_amp_time + MIN_RAMP_DURATION,
+            "{}", ERR55_RAMP_LOCKED
+        );
+        assert!(
+            future_amp_time >= current_ts + MIN_RAMP_DURATION,
+            "{}", ERR56_INSUFFICIENT_RAMP_TIME
+        );
+        assert!((MIN_AMP..=MAX_AMP).contains(&future_amp_factor), "{}", ERR54_INVALID_AMP);
+        let amp_factor = self.get_amp();
+        assert!(
+            future_amp_factor <= amp_factor * MAX_AMP_CHANGE
+                && future_amp_factor * MAX_AMP_CHANGE >= amp_factor,
+            "{}", ERR57_AMP_CHANGE_TOO_LARGE
+        );
+        self.init_amp_factor = amp_factor;
+        self.init_amp_time = current_ts;
+        self.target_amp_factor = future_amp_factor;
+        self.stop_amp_time = future_amp_time;
+    }
+
+    /// Stops ramping amplification coefficient at its current value.
+    pub fn stop_ramp_amplification(&mut self) {
+        let current_ts = env::block_timestamp();
+        let amp_factor = self.get_amp();
+        self.init_amp_factor = amp_factor;
+        self.target_amp_factor = amp_factor;
+        self.init_amp_time = current_ts;
+        self.stop_amp_time = current_ts;
+    }
+
+    /// Multiplier converting amount of given token into comparable amount.
+    fn decimal_factor(&self, token_idx: usize) -> Balance {
+        10u128.pow((TARGET_DECIMAL - self.token_decimals[token_idx]) as u32)
+    }
+
+    /// Converts amounts in tokens' own decimals into comparable amounts.
+    fn to_c_amounts(&self, amounts: &[Balance]) -> Vec<Balance> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(idx, amount)| {
+                amount
+                    .checked_mul(self.decimal_factor(idx))
+                    .expect(ERR59_AMOUNT_OVERFLOW)
+            })
+            .collect()
+    }
+
+    fn invariant(&self) -> StableSwap {
+        StableSwap::new(self.get_amp())
+    }
+
+    /// Rates of tokens in a pool without external prices.
+    fn unit_rates(&self) -> Vec<Balance> {
+        vec![RATE_DIVISOR; self.token_account_ids.len()]
+    }
+
+    /// Applies token rates to comparable amounts, these are the amounts the invariant works with.
+    fn to_rated_amounts(c_amounts: &[Balance], rates: &[Balance]) -> Vec<Balance> {
+        c_amounts
+            .iter()
+            .zip(rates.iter())
+            .map(|(c_amount, rate)| to_rated_amount(*c_amount, *rate))
+            .collect()
+    }
+
+    /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
+    /// Amounts not in the current pool proportion are charged with fee,
+    /// exchange's part of it is minted as shares to the exchange.
+    pub fn add_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: &mut [Balance],
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let rates = self.unit_rates();
+        self.add_liquidity_with_rates(sender_id, amounts, admin_fee, &rates)
+    }
+
+    /// Same as `add_liquidity`, with amounts valued by given token rates.
+    #[allow(clippy::needless_range_loop)]
+    pub(crate) fn add_liquidity_with_rates(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: &mut [Balance],
+        admin_fee: &AdminFees,
+        rates: &[Balance],
+    ) -> Balance {
+        assert_eq!(
+            amounts.len(),
+            self.token_account_ids.len(),
+            "{}", ERR30_WRONG_TOKEN_COUNT
+        );
+        let c_amounts = self.to_c_amounts(amounts);
+        let rated_amounts = Self::to_rated_amounts(&c_amounts, rates);
+        let shares = if self.shares_total_supply > 0 {
+            let (mint_shares, fee_part) = self
+                .invariant()
+                .compute_lp_amount_for_deposit(
+                    &rated_amounts,
+                    &Self::to_rated_amounts(&self.c_amounts, rates),
+                    self.shares_total_supply,
+                    &Fees::new(self.total_fee),
+                )
+                .expect(ERR38_LP_SHARES_CALC);
+            self.mint_fee_part(admin_fee, fee_part);
+            mint_shares
+        } else {
+            // Bootstrapping the pool requires all non-zero balances and is fee free.
+            for c_amount in c_amounts.iter() {
+                assert!(*c_amount > 0, "{}", ERR31_ZERO_AMOUNT);
+            }
+            let d = self
+                .invariant()
+                .compute_d(&rated_amounts)
+                .expect(ERR67_INVARIANT_CALC)
+                .as_u128();
+            assert!(d > MIN_LIQUIDITY, "{}", ERR37_MIN_LIQUIDITY);
+            self.mint_shares(&env::current_account_id(), MIN_LIQUIDITY);
+            self.locked_shares = MIN_LIQUIDITY;
+            d - MIN_LIQUIDITY
+        };
+        assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
+        for i in 0..self.token_account_ids.len() {
+            self.c_amounts[i] += c_amounts[i];
+        }
+        self.mint_shares(sender_id, shares);
+        shares
+    }
+
+    /// Mint new shares for given user.
+    fn mint_shares(&mut self, account_id: &AccountId, shares: Balance) {
+        if shares == 0 {
+            return;
+        }
+        self.shares_total_supply += shares;
+        add_to_collection(&mut self.shares, account_id, shares);
+    }
+
+    /// Mints exchange's part of the fee charged on imbalanced liquidity change.
+    fn mint_fee_part(&mut self, admin_fee: &AdminFees, fee_part: Balance) {
+        let exchange_shares = (U256::from(fee_part) * U256::from(admin_fee.exchange_fee)
+            / U256::from(FEE_DIVISOR))
+        .as_u128();
+        self.mint_shares(&admin_fee.exchange_id, exchange_shares);
+    }
+
+    /// Removes given number of shares from the pool proportionally and returns amounts to the parent.
+    pub fn remove_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        min_amounts: Vec<Balance>,
+    ) -> Vec<Balance> {
+        assert_eq!(
+            min_amounts.len(),
+            self.token_account_ids.len(),
+            "{}", ERR30_WRONG_TOKEN_COUNT
+        );
+        let prev_shares_amount = self.shares.get(sender_id).expect(ERR33_NO_SHARES);
+        assert!(prev_shares_amount >= shares, "{}", ERR34_NOT_ENOUGH_SHARES);
+        assert!(
+            self.shares_total_supply - shares >= self.locked_shares,
+            "{}", ERR37_MIN_LIQUIDITY
+        );
+        let mut result = vec![];
+        for (idx, min_amount) in min_amounts.iter().enumerate() {
+            let factor = self.decimal_factor(idx);
+            let amount = (U256::from(self.c_amounts[idx] / factor) * U256::from(shares)
+                / U256::from(self.shares_total_supply))
+            .as_u128();
+            assert!(amount >= *min_amount, "{}", ERR35_MIN_AMOUNT);
+            self.c_amounts[idx] -= amount * factor;
+            result.push(amount);
+        }
+        self.shares.insert(sender_id, &(prev_shares_amount - shares));
+        self.shares_total_supply -= shares;
+        result
+    }
+
+    /// Removes given amounts of tokens from the pool and returns number of shares burned.
+    /// Imbalance against the current pool proportion is charged with fee.
+    pub fn remove_liquidity_by_tokens(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: Vec<Balance>,
+        max_burn_shares: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let rates = self.unit_rates();
+        self.remove_liquidity_by_tokens_with_rates(sender_id, amounts, max_burn_shares, admin_fee, &rates)
+    }
+
+    /// Same as `remove_liquidity_by_tokens`, with amounts valued by given token rates.
+    #[allow(clippy::needless_range_loop)]
+    pub(crate) fn remove_liquidity_by_tokens_with_rates(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: Vec<Balance>,
+        max_burn_shares: Balance,
+        admin_fee: &AdminFees,
+        rates: &[Balance],
+    ) -> Balance {
+        assert_eq!(
+            amounts.len(),
+            self.token_account_ids.len(),
+            "{}", ERR30_WRONG_TOKEN_COUNT
+        );
+        let prev_shares_amount = self.shares.get(sender_id).expect(ERR33_NO_SHARES);
+        let c_amounts = self.to_c_amounts(&amounts);
+        let (burn_shares, fee_part) = self
+            .invariant()
+            .compute_lp_amount_for_withdraw(
+                &Self::to_rated_amounts(&c_amounts, rates),
+                &Self::to_rated_amounts(&self.c_amounts, rates),
+                self.shares_total_supply,
+                &Fees::new(self.total_fee),
+            )
+            .expect(ERR38_LP_SHARES_CALC);
+        assert!(burn_shares > 0, "{}", ERR39_BURN_ZERO_SHARES);
+        assert!(burn_shares <= max_burn_shares, "{}", ERR36_SLIPPAGE);
+        assert!(prev_shares_amount >= burn_shares, "{}", ERR34_NOT_ENOUGH_SHARES);
+        assert!(
+            self.shares_total_supply - burn_shares >= self.locked_shares,
+            "{}", ERR37_MIN_LIQUIDITY
+        );
+        for i in 0..self.token_account_ids.len() {
+            self.c_amounts[i] -= c_amounts[i];
+        }
+        self.shares.insert(sender_id, &(prev_shares_amount - burn_shares));
+        self.shares_total_supply -= burn_shares;
+        self.mint_fee_part(admin_fee, fee_part);
+        burn_shares
+    }
+
+    /// Returns token index for given pool.
+    fn token_index(&self, token_id: &AccountId) -> usize {
+        self.token_account_ids
+            .iter()
+            .position(|id| id == token_id)
+            .expect(ERR41_MISSING_TOKEN)
+    }
+
+    /// Returns number of tokens in outcome and the swap result in rated amounts.
+    /// Tokens are provided as indexes into token list for given pool.
+    fn internal_get_return(
+        &self,
+        token_in: usize,
+        amount_in: Balance,
+        token_out: usize,
+        rates: &[Balance],
+    ) -> (Balance, SwapResult) {
+        assert!(
+            token_in != token_out && amount_in > 0 && self.shares_total_supply > 0,
+            "{}", ERR48_INVALID_SWAP
+        );
+        let c_amount_in = amount_in
+            .checked_mul(self.decimal_factor(token_in))
+            .expect(ERR59_AMOUNT_OVERFLOW);
+        let result = self
+            .invariant()
+            .swap_to(
+                token_in,
+                to_rated_amount(c_amount_in, rates[token_in]),
+                token_out,
+                &Self::to_rated_amounts(&self.c_amounts, rates),
+                &Fees::new(self.total_fee),
+            )
+            .expect(ERR47_SWAP_CALC);
+        let c_amount_out = from_rated_amount(result.amount_swapped, rates[token_out]);
+        (c_amount_out / self.decimal_factor(token_out), result)
+    }
+
+    /// Returns how much token you will receive if swap `token_amount_in` of `token_in` for `token_out`.
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.get_return_with_rates(token_in, amount_in, token_out, &self.unit_rates())
+    }
+
+    /// Same as `get_return`, with amounts valued by given token rates.
+    pub(crate) fn get_return_with_rates(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        rates: &[Balance],
+    ) -> Balance {
+        self.internal_get_return(
+            self.token_index(token_in),
+            amount_in,
+            self.token_index(token_out),
+            rates,
+        )
+        .0
+    }
+
+    /// Returns how much `token_out` one unit of `token_in` is worth at current reserves, excluding fees.
+    /// Multiplied by PRICE_PRECISION.
+    pub fn get_spot_price(&self, token_in: &AccountId, token_out: &AccountId) -> Balance {
+        self.get_spot_price_with_rates(token_in, token_out, &self.unit_rates())
+    }
+
+    /// Same as `get_spot_price`, with amounts valued by given token rates.
+    pub(crate) fn get_spot_price_with_rates(
+        &self,
+        token_in: &AccountId,
+        token_out: &AccountId,
+        rates: &[Balance],
+    ) -> Balance {
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        assert!(
+            self.c_amounts[in_idx] > 0 && self.c_amounts[out_idx] > 0 && in_idx != out_idx,
+            "{}", ERR48_INVALID_SWAP
+        );
+        let rated_price = self
+            .invariant()
+            .compute_spot_price(&Self::to_rated_amounts(&self.c_amounts, rates), in_idx, out_idx)
+            .expect(ERR47_SWAP_CALC);
+        // Price of rated amounts back to the tokens' own decimals.
+        (rated_price * U256::from(self.decimal_factor(in_idx)) * U256::from(rates[in_idx])
+            / (U256::from(self.decimal_factor(out_idx)) * U256::from(rates[out_idx])))
+        .as_u128()
+    }
+
+    /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
+    /// Assuming that `token_amount_in` was already received from `sender_id`.
+    /// Exchange and referral parts of the fee are minted to them as LP shares.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let rates = self.unit_rates();
+        self.swap_with_rates(token_in, amount_in, token_out, min_amount_out, admin_fee, &rates)
+    }
+
+    /// Same as `swap`, with amounts valued by given token rates.
+    pub(crate) fn swap_with_rates(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+        rates: &[Balance],
+    ) -> Balance {
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let (amount_out, result) = self.internal_get_return(in_idx, amount_in, out_idx, rates);
+        assert!(amount_out >= min_amount_out, "{}", ERR35_MIN_AMOUNT);
+
+        // Only the amount actually sent out leaves the pool, rounding dust stays with LPs.
+        self.c_amounts[in_idx] += amount_in * self.decimal_factor(in_idx);
+        self.c_amounts[out_idx] -= amount_out * self.decimal_factor(out_idx);
+
+        // Exchange and referral parts of the fee are already in the pool, convert them into shares.
+        let fee = from_rated_amount(result.fee, rates[out_idx]);
+        let exchange_fee = (U256::from(fee) * U256::from(admin_fee.exchange_fee)
+            / U256::from(FEE_DIVISOR))
+        .as_u128();
+        self.admin_fee_to_liquidity(&admin_fee.exchange_id, out_idx, exchange_fee, rates);
+        if let Some(referral_id) = &admin_fee.referral_id {
+            if self.shares.contains_key(referral_id) {
+                let referral_fee = (U256::from(fee) * U256::from(admin_fee.referral_fee)
+                    / U256::from(FEE_DIVISOR))
+                .as_u128();
+                self.admin_fee_to_liquidity(referral_id, out_idx, referral_fee, rates);
+            }
+        }
+
+        // Keeping track of volume per each input traded separately.
+        self.volumes[in_idx].input.0 += amount_in;
+        self.volumes[out_idx].output.0 += amount_out;
+        self.fees[out_idx] += fee / self.decimal_factor(out_idx);
+
+        amount_out
+    }
+
+    /// Mints shares to given account for given comparable amount of token,
+    /// which is already part of the pool, as if it was deposited without fee.
+    fn admin_fee_to_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        token_idx: usize,
+        c_amount: Balance,
+        rates: &[Balance],
+    ) {
+        if c_amount == 0 {
+            return;
+        }
+        let mut old_c_amounts = self.c_amounts.clone();
+        old_c_amounts[token_idx] -= c_amount;
+        let mut deposit_c_amounts = vec![0u128; self.c_amounts.len()];
+        deposit_c_amounts[token_idx] = c_amount;
+        // Amount too small to move the invariant gives no shares.
+        if let Some((shares, _)) = self.invariant().compute_lp_amount_for_deposit(
+            &Self::to_rated_amounts(&deposit_c_amounts, rates),
+            &Self::to_rated_amounts(&old_c_amounts, rates),
+            self.shares_total_supply,
+            &Fees::zero(),
+        ) {
+            self.mint_shares(account_id, shares);
+        }
+    }
+
+    /// Returns given pool's total fee.
+    pub fn get_fee(&self) -> u32 {
+        self.total_fee
+    }
+
+    /// Returns volumes of the given pool.
+    pub fn get_volumes(&self) -> Vec<SwapVolume> {
+        self.volumes.clone()
+    }
+
+    /// Returns fees accumulated by the given pool.
+    pub fn get_fees(&self) -> Vec<Balance> {
+        self.fees.clone()
+    }
+}
+
+/// Converts comparable amount into amount valued by given rate.
+fn to_rated_amount(c_amount: Balance, rate: Balance) -> Balance {
+    (U256::from(c_amount) * U256::from(rate) / U256::from(RATE_DIVISOR)).as_u128()
+}
+
+/// Converts amount valued by given rate back into comparable amount.
+fn from_rated_amount(rated_amount: Balance, rate: Balance) -> Balance {
+    (U256::from(rated_amount) * U256::from(RATE_DIVISOR) / U256::from(rate)).as_u128()
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    const ONE_USDC: Balance = 1_000_000;
+    const ONE_DAI: Balance = 1_000_000_000_000_000_000;
+
+    #[test]
+    fn test_round_trip_mixed_decimals() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.current_account_id(accounts(0)).build());
+        let mut pool = StableSwapPool::new(0, vec![accounts(1), accounts(2)], vec![6, 18], 100, 5);
+        let user: AccountId = accounts(3).into();
+        pool.share_register(&accounts(0).into());
+        pool.share_register(&user);
+        let admin_fee = AdminFees::new(0, 0, accounts(0).into(), None);
+
+        // Balanced deposit mints shares equal to the invariant in 24 decimals.
+        let shares = pool.add_liquidity(&user, &mut [1_000_000 * ONE_USDC, 1_000_000 * ONE_DAI], &admin_fee);
+        assert_eq!(shares, 2_000_000 * 10u128.pow(24) - MIN_LIQUIDITY);
+        assert_eq!(pool.share_total_balance(), shares + MIN_LIQUIDITY);
+        assert_eq!(pool.get_amounts(), vec![1_000_000 * ONE_USDC, 1_000_000 * ONE_DAI]);
+
+        let amount_out = pool.swap(&accounts(1).into(), 1_000 * ONE_USDC, &accounts(2).into(), 0, &admin_fee);
+        assert!(amount_out > 999 * ONE_DAI && amount_out < 1_000 * ONE_DAI);
+        assert_eq!(
+            pool.get_amounts(),
+            vec![1_001_000 * ONE_USDC, 1_000_000 * ONE_DAI - amount_out]
+        );
+
+        let pool_amounts = pool.get_amounts();
+        let total_shares = pool.share_total_balance();
+        let amounts = pool.remove_liquidity(&user, shares, vec![0, 0]);
+        for (amount, pool_amount) in amounts.iter().zip(pool_amounts.iter()) {
+            assert_eq!(
+                *amount,
+                (U256::from(*pool_amount) * U256::from(shares) / U256::from(total_shares)).as_u128()
+            );
+        }
+        assert_eq!(pool.share_total_balance(), MIN_LIQUIDITY);
+        assert_eq!(pool.share_balance_of(&user), 0);
+    }
+}
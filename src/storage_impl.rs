@@ -1,5 +1,10 @@
+use crate::events::Event;
 use crate::*;
 
+/// Max number of tokens force unregister sends out,
+/// each transfer takes `GAS_FOR_FT_TRANSFER` and `GAS_FOR_RESOLVE_TRANSFER`.
+const MAX_FORCE_UNREGISTER_TOKENS: usize = 6;
+
 #[near_bindgen]
 impl StorageManagement for Contract {
     #[payable]
@@ -10,9 +15,9 @@ impl StorageManagement for Contract {
             .unwrap_or_else(|| env::predecessor_account_id());
         let registration_only = registration_only.unwrap_or(false);
         let min_balance = self.storage_balance_bounds().min.0;
-        let already_registered = self.accounts.contains_key(&account_id);
+        let already_registered = self.internal_is_registered(&account_id);
         if amount < min_balance && !already_registered {
-            env::panic(b"deposit less than min storage");
+            env::panic(ERR12_STORAGE_DEPOSIT_LESS_THAN_MIN.as_bytes());
         }
         if registration_only {
             if already_registered {
@@ -22,6 +27,11 @@ impl StorageManagement for Contract {
                 }
             } else {
                 self.internal_register_account(&account_id, min_balance);
+                Event::StorageDeposit {
+                    account_id: &account_id,
+                    amount: U128(min_balance),
+                }
+                .emit();
                 let refund = amount - min_balance;
                 if refund > 0 {
                     Promise::new( env::predecessor_account_id()).transfer(refund);
@@ -29,6 +39,11 @@ impl StorageManagement for Contract {
             }
         } else {
             self.internal_register_account(&account_id, amount);
+            Event::StorageDeposit {
+                account_id: &account_id,
+                amount: U128(amount),
+            }
+            .emit();
         }
         self.storage_balance_of(account_id.try_into().unwrap())
             .unwrap()
@@ -39,22 +54,44 @@ impl StorageManagement for Contract {
         let account_id = env::predecessor_account_id();
         let amount = amount.unwrap_or(U128(0)).0;
         let withdraw_amount = self.internal_storage_withdraw(&account_id, amount);
+        Event::StorageWithdraw {
+            account_id: &account_id,
+            amount: U128(withdraw_amount),
+        }
+        .emit();
         Promise::new(account_id.clone()).transfer(withdraw_amount);
         self.storage_balance_of(account_id.try_into().unwrap())
             .unwrap()
     }
 
-    #[allow(unused_variables)]
+    /// Unregisters the predecessor and refunds its storage deposit, its empty LP share entries are removed.
+    /// Without `force` all token deposits must be withdrawn and all liquidity removed before.
+    /// With `force` all LP shares are burned, then all non-zero deposits, including the removed liquidity,
+    /// are sent back to the account, undelivered ones go to lost-found. At most 6 tokens can be sent.
     #[payable]
     fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
         let account_id = env::predecessor_account_id();
-        if let Some(account_deposit) = self.internal_get_account(&account_id) {
-            // TODO: figure out force option logic.
+        if let Some(mut account_deposit) = self.internal_get_account(&account_id) {
+            if force.unwrap_or(false) {
+                self.internal_force_remove_liquidity(&account_id, &mut account_deposit);
+                self.internal_force_unregister(&account_id, &mut account_deposit);
+            }
+            self.internal_unregister_shares(&account_id);
             assert!(
                 account_deposit.tokens.is_empty(),
-                "ERR_STORAGE_UNREGISTER_TOKENS_NOT_EMPTY"
+                "{}", ERR18_TOKENS_NOT_EMPTY
             );
+            account_deposit.whitelisted_tokens.clear();
             self.accounts.remove(&account_id);
+            self.legacy_accounts.remove(&account_id);
+            self.account_pools.remove(&account_id);
+            self.account_ids.remove(&account_id);
+            Event::StorageUnregister {
+                account_id: &account_id,
+                amount: U128(account_deposit.near_amount),
+            }
+            .emit();
             Promise::new(account_id.clone()).transfer(account_deposit.near_amount);
             true
         } else {
@@ -78,4 +115,63 @@ impl StorageManagement for Contract {
                 }
             })
     }
-}
\ No newline at end of file
+}
+
+impl Contract {
+    /// Removes LP share entries of the account, all of them must be empty.
+    fn internal_unregister_shares(&mut self, account_id: &AccountId) {
+        for pool_id in self.internal_get_account_pools(account_id) {
+            let mut pool = self.internal_get_pool(pool_id).unwrap();
+            assert_eq!(pool.share_balances(account_id), 0, "{}", ERR27_SHARES_NOT_EMPTY);
+            if pool.share_unregister(account_id) {
+                self.internal_save_pool(pool_id, pool);
+            }
+        }
+    }
+
+    /// Burns all LP shares of the account, the removed liquidity is added to its deposits.
+    fn internal_force_remove_liquidity(&mut self, account_id: &AccountId, account: &mut Account) {
+        for pool_id in self.internal_get_account_pools(account_id) {
+            let mut pool = self.internal_get_pool(pool_id).unwrap();
+            let shares = pool.share_balances(account_id);
+            if shares == 0 {
+                continue;
+            }
+            let amounts = pool.remove_liquidity(account_id, shares, vec![0; pool.tokens().len()]);
+            for (token_id, amount) in pool.tokens().iter().zip(amounts.iter()) {
+                account.deposit(token_id, *amount);
+            }
+            self.internal_save_pool(pool_id, pool);
+            Event::RemoveLiquidity {
+                account_id,
+                pool_id,
+                amounts: amounts.into_iter().map(U128).collect(),
+                shares: U128(shares),
+            }
+            .emit();
+        }
+    }
+
+    /// Sends out all token deposits of the account.
+    fn internal_force_unregister(&mut self, account_id: &AccountId, account: &mut Account) {
+        let deposits: Vec<(AccountId, Balance)> = account
+            .tokens
+            .iter()
+            .filter(|(_, amount)| *amount > 0)
+            .collect();
+        assert!(
+            deposits.len() <= MAX_FORCE_UNREGISTER_TOKENS,
+            "{}", ERR80_TOO_MANY_TOKENS
+        );
+        for (token_id, amount) in deposits {
+            Event::Withdraw {
+                account_id,
+                token_id: &token_id,
+                amount: U128(amount),
+            }
+            .emit();
+            self.internal_send_tokens(account_id, &token_id, amount);
+        }
+        account.tokens.clear();
+    }
+}
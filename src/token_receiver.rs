@@ -0,0 +1,111 @@
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use near_sdk::PromiseOrValue;
+
+use crate::*;
+
+/// Message parameters to receive via token function call.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(untagged)]
+enum TokenReceiverMessage {
+    /// Alternative to deposit + execute actions call.
+    Execute {
+        referral_id: Option<ValidAccountId>,
+        /// List of sequential actions.
+        actions: Vec<SwapAction>,
+    },
+    /// Deposit to the internal account of `deposit_to` instead of the sender.
+    DepositTo { deposit_to: ValidAccountId },
+}
+
+impl Contract {
+    /// Executes swap actions on the tokens just transferred, without touching internal deposits.
+    /// First action may take part of the transferred amount, all following actions must be chained.
+    /// Returns received token, amount of it and how much of `amount_in` was used.
+    fn internal_direct_actions(
+        &mut self,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        amount_in: Balance,
+        referral_id: Option<ValidAccountId>,
+        actions: &[SwapAction],
+    ) -> (AccountId, Balance, Balance) {
+        let (first, rest) = actions.split_first().expect(ERR40_AT_LEAST_ONE_SWAP);
+        assert_eq!(first.token_in.as_ref(), token_in, "{}", ERR43_WRONG_CHAINED_TOKEN);
+        let used = first.amount_in.map(|amount| amount.0).unwrap_or(amount_in);
+        assert!(used <= amount_in, "{}", ERR45_AMOUNT_IN_TOO_LARGE);
+        let admin_fee = self.internal_admin_fees(referral_id);
+        let mut result = self.internal_execute_swap(sender_id, first, used, &admin_fee);
+        for action in rest {
+            assert!(action.amount_in.is_none(), "{}", ERR44_INSTANT_SWAP_NOT_CHAINED);
+            assert_eq!(action.token_in.as_ref(), &result.0, "{}", ERR43_WRONG_CHAINED_TOKEN);
+            result = self.internal_execute_swap(sender_id, action, result.1, &admin_fee);
+        }
+        (result.0, result.1, used)
+    }
+
+    /// Deposits transferred tokens to the given account, or refunds them if the account is not registered.
+    fn internal_deposit_or_refund(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: U128,
+    ) -> PromiseOrValue<U128> {
+        if self.internal_get_account(account_id).is_none() {
+            log!("Account {} is not registered, refunding deposit", account_id);
+            return PromiseOrValue::Value(amount);
+        }
+        // Refund deposits of tokens the user didn't opt into, so spam tokens can't take their storage.
+        if !self.internal_is_token_accepted(account_id, token_id) {
+            log!("Token {} is not whitelisted, refunding deposit", token_id);
+            return PromiseOrValue::Value(amount);
+        }
+        self.internal_save_information_to_contract(account_id, token_id, amount.into());
+        PromiseOrValue::Value(U128(0))
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// Callback on receiving tokens by this contract.
+    /// `msg` format is either "" for deposit or `TokenReceiverMessage`.
+    /// For instant swap the output is sent back to `sender_id` and unused input is refunded.
+    /// Deposits to an unregistered account or of a token it doesn't accept are refunded in full.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_contract_running();
+        let token_id = env::predecessor_account_id();
+        if msg.is_empty() {
+            self.internal_deposit_or_refund(sender_id.as_ref(), &token_id, amount)
+        } else {
+            let message =
+                serde_json::from_str::<TokenReceiverMessage>(&msg).expect(ERR28_WRONG_MSG_FORMAT);
+            match message {
+                TokenReceiverMessage::Execute {
+                    referral_id,
+                    actions,
+                } => {
+                    let (token_out, amount_out, used) =
+                        self.internal_direct_actions(sender_id.as_ref(), &token_id, amount.0, referral_id, &actions);
+                    Event::Withdraw {
+                        account_id: sender_id.as_ref(),
+                        token_id: &token_out,
+                        amount: U128(amount_out),
+                    }
+                    .emit();
+                    self.internal_send_tokens(sender_id.as_ref(), &token_out, amount_out);
+                    PromiseOrValue::Value(U128(amount.0 - used))
+                }
+                TokenReceiverMessage::DepositTo { deposit_to } => {
+                    self.internal_deposit_or_refund(deposit_to.as_ref(), &token_id, amount)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! Implement all the relevant logic for smart contract upgrade.
+
+use crate::*;
+use crate::utils::SwapVolume;
+
+/// Simple pool layout of the first deployment, before fees were accumulated.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SimplePoolV0 {
+    pub token_account_ids: Vec<AccountId>,
+    pub amounts: Vec<Balance>,
+    pub volumes: Vec<SwapVolume>,
+    pub total_fee: u32,
+    pub exchange_fee: u32,
+    pub referral_fee: u32,
+    pub shares: LookupMap<AccountId, Balance>,
+    pub shares_total_supply: Balance,
+}
+
+impl From<SimplePoolV0> for SimplePool {
+    fn from(pool: SimplePoolV0) -> Self {
+        SimplePool {
+            fees: vec![0; pool.token_account_ids.len()],
+            token_account_ids: pool.token_account_ids,
+            amounts: pool.amounts,
+            volumes: pool.volumes,
+            total_fee: pool.total_fee,
+            exchange_fee: pool.exchange_fee,
+            referral_fee: pool.referral_fee,
+            shares: pool.shares,
+            shares_total_supply: pool.shares_total_supply,
+            locked_shares: 0,
+        }
+    }
+}
+
+/// Pool layout of the first deployment, stored without `VPool`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum PoolV0 {
+    SimplePool(SimplePoolV0),
+}
+
+/// Contract layout of the first deployment.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ContractV0 {
+    pub owner_id: AccountId,
+    pub accounts: LookupMap<AccountId, AccountV0>,
+    pub whitelisted_tokens: UnorderedSet<AccountId>,
+    pub exchange_fee: u32,
+    pub referral_fee: u32,
+    pub pools: Vector<PoolV0>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Migration function between versions, called by `upgrade` after the new code is deployed.
+    /// `near_bindgen` reads the state as `Contract` directly, so when its layout changes, keep the
+    /// previous layout here as `ContractV{n}`, read it instead of `Contract` and convert it.
+    /// Pools are few and are converted here. Accounts are too many for one call, they stay in
+    /// `legacy_accounts` and are moved to the versioned accounts when they are saved next time.
+    #[init(ignore_state)]
+    #[private]
+    pub fn migrate() -> Self {
+        let contract: ContractV0 = env::state_read().expect(ERR95_NOT_INITIALIZED);
+        let mut pools: Vector<VPool> = Vector::new(StorageKey::Pools);
+        for pool_id in 0..contract.pools.len() {
+            let PoolV0::SimplePool(pool) = contract.pools.get(pool_id).unwrap();
+            let mut pool = SimplePool::from(pool);
+            // exchange share is registered at creation time since the first deployment
+            if !pool.shares.contains_key(&env::current_account_id()) {
+                pool.share_register(&env::current_account_id());
+            }
+            // Same prefix and length, so this overwrites the old pool in place.
+            pools.push(&Pool::SimplePool(pool).into());
+        }
+        Contract {
+            owner_id: contract.owner_id,
+            pending_owner_id: None,
+            guardians: UnorderedSet::new(StorageKey::Guardian),
+            accounts: LookupMap::new(StorageKey::VersionedAccounts),
+            legacy_accounts: contract.accounts,
+            account_pools: LookupMap::new(StorageKey::AccountPools),
+            legacy_pool_count: pools.len(),
+            rated_pools: LookupMap::new(StorageKey::RatedPools),
+            whitelisted_tokens: contract.whitelisted_tokens,
+            whitelisted_pool_tokens_only: false,
+            exchange_fee: contract.exchange_fee,
+            referral_fee: contract.referral_fee,
+            pools,
+            state: RunningState::Running,
+            frozen_pools: UnorderedSet::new(StorageKey::FrozenPools),
+            frozen_tokens: UnorderedSet::new(StorageKey::FrozenTokens),
+            account_ids: UnorderedSet::new(StorageKey::AccountIds),
+        }
+    }
+
+    /// Adds given legacy accounts to the registry of account ids, used by `get_user_count` and
+    /// `list_accounts`. Legacy accounts can't be listed on chain, so the owner passes them in pages.
+    /// Accounts moved to the versioned accounts are registered already, other ids are skipped.
+    /// Only can be called by owner.
+    #[payable]
+    pub fn backfill_account_ids(&mut self, account_ids: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        for account_id in account_ids {
+            if self.legacy_accounts.contains_key(account_id.as_ref()) {
+                self.account_ids.insert(account_id.as_ref());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    #[test]
+    fn test_migrate_from_v0() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .build());
+        let user: AccountId = accounts(1).into();
+        let mut tokens = UnorderedMap::new(StorageKey::AccountTokens {
+            account_id: user.clone(),
+        });
+        tokens.insert(&accounts(2).into(), &100);
+        let mut old_accounts = LookupMap::new(StorageKey::Accounts);
+        old_accounts.insert(
+            &user,
+            &AccountV0 {
+                near_amount: 10u128.pow(24),
+                tokens,
+            },
+        );
+        let mut shares = LookupMap::new(StorageKey::Shares { pool_id: 0 });
+        shares.insert(&user, &500);
+        let mut old_pools = Vector::new(StorageKey::Pools);
+        old_pools.push(&PoolV0::SimplePool(SimplePoolV0 {
+            token_account_ids: vec![accounts(2).into(), accounts(3).into()],
+            amounts: vec![1000, 2000],
+            volumes: vec![SwapVolume::default(); 2],
+            total_fee: 30,
+            exchange_fee: 0,
+            referral_fee: 0,
+            shares,
+            shares_total_supply: 500,
+        }));
+        env::state_write(&ContractV0 {
+            owner_id: accounts(0).into(),
+            accounts: old_accounts,
+            whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
+            exchange_fee: 5,
+            referral_fee: 1,
+            pools: old_pools,
+        });
+
+        let mut contract = Contract::migrate();
+        assert_eq!(contract.get_number_of_pools(), 1);
+        let pool = contract.get_pool(0);
+        assert_eq!(pool.amounts, vec![U128(1000), U128(2000)]);
+        assert_eq!(pool.total_fee, 30);
+        assert_eq!(pool.min_liquidity, U128(0));
+        assert_eq!(contract.get_pool_fees(0), vec![U128(0), U128(0)]);
+        assert_eq!(contract.get_pool_shares(0, accounts(1)).0, 500);
+        assert_eq!(contract.get_pool_shares(0, accounts(0)).0, 0);
+        assert_eq!(contract.get_token_balance(accounts(1), accounts(2)).0, 100);
+
+        assert_eq!(contract.internal_get_account_pools(&user), vec![0]);
+
+        // Migrated pools never locked any shares, so all liquidity can be removed.
+        let mut pool = contract.internal_get_pool(0).unwrap();
+        assert_eq!(pool.remove_liquidity(&user, 500, vec![0, 0]), vec![1000, 2000]);
+        assert_eq!(pool.share_total_balance(), 0);
+
+        // Legacy accounts are missing from the registry until the owner backfills them.
+        assert_eq!(contract.get_user_count(), 0);
+        testing_env!(context.attached_deposit(1).build());
+        contract.backfill_account_ids(vec![accounts(1), accounts(4)]);
+        assert_eq!(contract.list_accounts(0, 10), vec![user.clone()]);
+
+        // Saving the account moves it out of the legacy accounts, keeping its pools tracked.
+        let prev_storage = env::storage_usage();
+        let account = contract.internal_unwrap_account(&user);
+        contract.internal_save_account(&user, account, prev_storage);
+        assert!(!contract.legacy_accounts.contains_key(&user));
+        assert!(contract.accounts.contains_key(&user));
+        assert_eq!(contract.internal_get_account_pools(&user), vec![0]);
+        assert_eq!(contract.list_accounts(0, 10), vec![user]);
+        assert_eq!(contract.get_token_balance(accounts(1), accounts(2)).0, 100);
+    }
+
+    #[test]
+    fn test_migrate_withdraw_from_v0_account() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .build());
+        let user: AccountId = accounts(1).into();
+        let mut tokens = UnorderedMap::new(StorageKey::AccountTokens {
+            account_id: user.clone(),
+        });
+        tokens.insert(&accounts(2).into(), &100);
+        let mut old_accounts = LookupMap::new(StorageKey::Accounts);
+        // Exactly the deposit the first deployment required for an account with one token.
+        old_accounts.insert(
+            &user,
+            &AccountV0 {
+                near_amount: 2_460_000_000_000_000_000_000,
+                tokens,
+            },
+        );
+        env::state_write(&ContractV0 {
+            owner_id: accounts(0).into(),
+            accounts: old_accounts,
+            whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
+            exchange_fee: 5,
+            referral_fee: 1,
+            pools: Vector::new(StorageKey::Pools),
+        });
+
+        let mut contract = Contract::migrate();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw(accounts(2), U128(40), None);
+        assert_eq!(contract.get_token_balance(accounts(1), accounts(2)).0, 60);
+        assert!(!contract.legacy_accounts.contains_key(&user));
+
+        // The moved record keeps the storage charged by the first deployment.
+        contract.withdraw(accounts(2), U128(60), Some(true));
+        assert_eq!(contract.get_deposits(accounts(1)).len(), 0);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod upgrade {
+    use near_sdk::env::BLOCKCHAIN_INTERFACE;
+    use near_sdk::Gas;
+
+    use super::*;
+
+    const BLOCKCHAIN_INTERFACE_NOT_SET_ERR: &str = "Blockchain interface not set.";
+
+    /// Gas for calling migration call.
+    pub const GAS_FOR_MIGRATE_CALL: Gas = 5_000_000_000_000;
+
+    /// Self upgrade and call migrate, optimizes gas by not loading into memory the code.
+    /// Takes as input non serialized set of bytes of the code.
+    /// Only can be called by owner.
+    #[no_mangle]
+    pub extern "C" fn upgrade() {
+        env::setup_panic_hook();
+        env::set_blockchain_interface(Box::new(near_blockchain::NearBlockchain {}));
+        let contract: Contract = env::state_read().expect(ERR95_NOT_INITIALIZED);
+        contract.assert_owner();
+        let current_id = env::current_account_id().into_bytes();
+        let method_name = "migrate".as_bytes().to_vec();
+        unsafe {
+            BLOCKCHAIN_INTERFACE.with(|b| {
+                let b = b.borrow();
+                let b = b.as_ref().expect(BLOCKCHAIN_INTERFACE_NOT_SET_ERR);
+                // Load input (the new code) into register 0.
+                b.input(0);
+                let promise_id =
+                    b.promise_batch_create(current_id.len() as _, current_id.as_ptr() as _);
+                // Deploy the code from register 0.
+                b.promise_batch_action_deploy_contract(promise_id, u64::MAX as _, 0);
+                let attached_gas = env::prepaid_gas() - env::used_gas() - GAS_FOR_MIGRATE_CALL;
+                b.promise_batch_action_function_call(
+                    promise_id,
+                    method_name.len() as _,
+                    method_name.as_ptr() as _,
+                    0 as _,
+                    0 as _,
+                    0 as _,
+                    attached_gas,
+                );
+            });
+        }
+    }
+}
@@ -8,10 +8,19 @@ use near_sdk::serde::{Serialize, Deserialize};
 
 use uint::construct_uint;
 
+use crate::errors::*;
+
 
 /// Fee divisor, allowing to provide fee in bps.
 pub const FEE_DIVISOR: u32 = 10_000;
 
+/// Precision of spot prices, price of one unit of token in another is multiplied by it.
+pub const PRICE_PRECISION: u128 = 1_000_000_000_000_000_000_000_000;
+
+/// Shares minted to the exchange account on the first deposit into a pool and never removed,
+/// so the pool can't be drained to dust and re-seeded at a different ratio.
+pub const MIN_LIQUIDITY: u128 = 1_000_000_000_000_000_000;
+
 construct_uint! {
     /// 256-bit unsigned integer.
     pub struct U256(4);
@@ -51,6 +60,20 @@ pub trait RefExchange {
         sender_id: AccountId,
         amount: U128,
     );
+    fn callback_update_token_rate(&mut self, token_id: AccountId);
+    fn mft_resolve_transfer(
+        &mut self,
+        token_id: String,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+}
+
+/// Contract reporting the rate of a token, e.g. liquid staking token contract.
+#[ext_contract(ext_rate_source)]
+pub trait RateSource {
+    fn ft_price(&self) -> U128;
 }
 
 /// Adds given value to item stored in the given key in the LookupMap collection.
@@ -62,5 +85,5 @@ pub fn add_to_collection(c: &mut LookupMap<AccountId, Balance>, key: &String, va
 /// Checks if there are any duplicates in the given list of tokens.
 pub fn check_token_duplicates(tokens: &[ValidAccountId]) {
     let token_set: HashSet<_> = tokens.iter().map(|a| a.as_ref()).collect();
-    assert_eq!(token_set.len(), tokens.len(), "ERR_TOKEN_DUPLICATES")
+    assert_eq!(token_set.len(), tokens.len(), "{}", ERR23_TOKEN_DUPLICATES)
 }
\ No newline at end of file
@@ -1,11 +1,8 @@
 use near_sdk::{serde::{Serialize, Deserialize}, AccountId, json_types::U128, near_bindgen};
 
-use crate::{pool::Pool, utils::SwapVolume};
-use crate::Account;
+use crate::{pool::Pool, utils::{SwapVolume, FEE_DIVISOR, PRICE_PRECISION, U256}};
 use crate::*;
 
-pub const ERR85_NO_POOL: &str = "E85: invalid pool id";
-
 
 
 #[derive(Serialize, Deserialize)]
@@ -23,6 +20,34 @@ pub struct PoolInfo {
     /// Total number of shares.
     pub shares_total_supply: U128,
     pub amp: u64,
+    /// `Paused` if the pool is frozen or contains a frozen token.
+    pub state: RunningState,
+    /// Shares locked forever on the first deposit, none for pools migrated from the first deployment.
+    pub min_liquidity: U128,
+}
+
+/// LP shares of a user in a pool, with amounts of tokens they are worth.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct UserPoolShares {
+    pub pool_id: u64,
+    pub shares: U128,
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// Amounts of tokens the shares are worth at current pool reserves.
+    pub amounts: Vec<U128>,
+}
+
+/// Everything a user holds in the exchange.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct UserPortfolio {
+    /// Deposits outside of any pools.
+    pub deposits: HashMap<AccountId, U128>,
+    /// Pools where the user holds shares.
+    pub pools: Vec<UserPoolShares>,
 }
 
 impl From<Pool> for PoolInfo {
@@ -36,7 +61,39 @@ impl From<Pool> for PoolInfo {
                 amounts: pool.amounts.into_iter().map(|a| U128(a)).collect(),
                 total_fee: pool.total_fee,
                 shares_total_supply: U128(pool.shares_total_supply),
-            }
+                state: RunningState::Running,
+                min_liquidity: U128(pool.locked_shares),
+            },
+            Pool::StableSwapPool(pool) => Self {
+                pool_kind,
+                amp: pool.get_amp() as u64,
+                amounts: pool.get_amounts().into_iter().map(U128).collect(),
+                token_account_ids: pool.token_account_ids,
+                total_fee: pool.total_fee,
+                shares_total_supply: U128(pool.shares_total_supply),
+                state: RunningState::Running,
+                min_liquidity: U128(pool.locked_shares),
+            },
+            Pool::RatedSwapPool(pool) => Self {
+                pool_kind,
+                amp: pool.pool.get_amp() as u64,
+                amounts: pool.pool.get_amounts().into_iter().map(U128).collect(),
+                token_account_ids: pool.pool.token_account_ids,
+                total_fee: pool.pool.total_fee,
+                shares_total_supply: U128(pool.pool.shares_total_supply),
+                state: RunningState::Running,
+                min_liquidity: U128(pool.pool.locked_shares),
+            },
+            Pool::WeightedPool(pool) => Self {
+                pool_kind,
+                amp: 0,
+                token_account_ids: pool.token_account_ids,
+                amounts: pool.amounts.into_iter().map(U128).collect(),
+                total_fee: pool.total_fee,
+                shares_total_supply: U128(pool.shares_total_supply),
+                state: RunningState::Running,
+                min_liquidity: U128(pool.locked_shares),
+            },
         }
     }
 }
@@ -52,39 +109,265 @@ impl Contract {
 
     /// Returns information about specified pool.
     pub fn get_pool(&self, pool_id: u64) -> PoolInfo {
-        self.pools.get(pool_id).expect("ERR_NO_POOL").into()
+        let pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        let state = self.internal_pool_state(pool_id, &pool);
+        PoolInfo {
+            state,
+            ..pool.into()
+        }
     }
 
     /// Return volumes of the given pool.
     pub fn get_pool_volumes(&self, pool_id: u64) -> Vec<SwapVolume> {
-        self.pools.get(pool_id).expect("ERR_NO_POOL").get_volumes()
+        self.internal_get_pool(pool_id).expect(ERR85_NO_POOL).get_volumes()
+    }
+
+    /// Return fees accumulated by the given pool, per token in the pool's token order.
+    /// Denomination follows how each pool kind charges the fee: simple and weighted pools
+    /// take it from the input token of a swap, stable and rated swap pools from the output token.
+    pub fn get_pool_fees(&self, pool_id: u64) -> Vec<U128> {
+        self.internal_get_pool(pool_id)
+            .expect(ERR85_NO_POOL)
+            .get_fees()
+            .into_iter()
+            .map(U128)
+            .collect()
     }
 
     /// Returns number of shares given account has in given pool.
     pub fn get_pool_shares(&self, pool_id: u64, account_id: ValidAccountId) -> U128 {
-        self.pools
-            .get(pool_id)
-            .expect("ERR_NO_POOL")
+        self.internal_get_pool(pool_id)
+            .expect(ERR85_NO_POOL)
             .share_balances(account_id.as_ref())
             .into()
     }
 
     /// Returns total number of shares in the given pool.
     pub fn get_pool_total_shares(&self, pool_id: u64) -> U128 {
-        self.pools
-            .get(pool_id)
+        self.internal_get_pool(pool_id)
             .expect(ERR85_NO_POOL)
             .share_total_balance()
             .into()
     }
 
+    /// Returns how much `token_out` would be received for `amount_in` of `token_in` in given pool.
+    pub fn get_return(
+        &self,
+        pool_id: u64,
+        token_in: ValidAccountId,
+        amount_in: U128,
+        token_out: ValidAccountId,
+    ) -> U128 {
+        self.internal_get_pool(pool_id)
+            .expect(ERR85_NO_POOL)
+            .get_return(token_in.as_ref(), amount_in.into(), token_out.as_ref())
+            .into()
+    }
+
+    /// Returns amounts received by each of given swap actions, chained the same way as in `swap`.
+    /// Every action is quoted at current state of its pool, so routes using the same pool twice are approximate.
+    pub fn get_returns_batch(&self, actions: Vec<SwapAction>) -> Vec<U128> {
+        let mut prev_amount: Option<Balance> = None;
+        actions
+            .iter()
+            .map(|action| {
+                let amount_in = action
+                    .amount_in
+                    .map(|amount_in| amount_in.0)
+                    .or(prev_amount)
+                    .expect(ERR42_NO_PREV_AMOUNT);
+                let amount_out = self
+                    .internal_get_pool(action.pool_id)
+                    .expect(ERR85_NO_POOL)
+                    .get_return(action.token_in.as_ref(), amount_in, action.token_out.as_ref());
+                prev_amount = Some(amount_out);
+                U128(amount_out)
+            })
+            .collect()
+    }
+
+    /// Returns how much `token_b` one unit of `token_a` is worth in given pool, excluding fees.
+    /// Multiplied by PRICE_PRECISION (10**24) and in the tokens' own decimals.
+    pub fn get_pool_spot_price(
+        &self,
+        pool_id: u64,
+        token_a: ValidAccountId,
+        token_b: ValidAccountId,
+    ) -> U128 {
+        self.internal_get_pool(pool_id)
+            .expect(ERR85_NO_POOL)
+            .get_spot_price(token_a.as_ref(), token_b.as_ref())
+            .into()
+    }
+
+    /// Returns how much worse than the spot price swapping `amount_in` of `token_in` is, including the pool fee.
+    /// In basis points of FEE_DIVISOR.
+    pub fn get_price_impact(
+        &self,
+        pool_id: u64,
+        token_in: ValidAccountId,
+        amount_in: U128,
+        token_out: ValidAccountId,
+    ) -> u32 {
+        let pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        let amount_out = pool.get_return(token_in.as_ref(), amount_in.0, token_out.as_ref());
+        let spot_amount_out = U256::from(amount_in.0)
+            * U256::from(pool.get_spot_price(token_in.as_ref(), token_out.as_ref()))
+            / U256::from(PRICE_PRECISION);
+        if spot_amount_out <= U256::from(amount_out) {
+            return 0;
+        }
+        ((spot_amount_out - U256::from(amount_out)) * U256::from(FEE_DIVISOR) / spot_amount_out)
+            .as_u32()
+    }
+
     /// Returns number of pools 
     pub fn get_number_of_pools(&self) -> u64 {
         self.pools.len()
     }
 
-    pub fn get_token_balance(&self, account_id: AccountId, token_id: AccountId) -> U128 {
-        let account = self.internal_get_account(&account_id).unwrap();
-        U128::from(account.get_balance(&token_id).unwrap())
+    /// Returns owner of this contract.
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    /// Returns account proposed as the new owner, if any.
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        self.pending_owner_id.clone()
+    }
+
+    /// Returns running state of the contract.
+    pub fn get_state(&self) -> RunningState {
+        self.state.clone()
+    }
+
+    /// Returns list of guardians.
+    pub fn get_guardians(&self) -> Vec<AccountId> {
+        self.guardians.to_vec()
+    }
+
+    /// Returns all error codes of the contract with their messages, e.g. "E85" -> "invalid pool id".
+    pub fn get_error_codes(&self) -> HashMap<String, String> {
+        ERRORS
+            .iter()
+            .filter_map(|error| error.split_once(": "))
+            .map(|(code, message)| (code.to_string(), message.to_string()))
+            .collect()
+    }
+
+    /// Returns list of frozen pools.
+    pub fn get_frozen_pools(&self) -> Vec<u64> {
+        self.frozen_pools.to_vec()
+    }
+
+    /// Returns list of frozen tokens.
+    pub fn get_frozen_tokens(&self) -> Vec<AccountId> {
+        self.frozen_tokens.to_vec()
+    }
+
+    /// Returns list of global whitelisted tokens.
+    pub fn get_whitelisted_tokens(&self) -> Vec<AccountId> {
+        self.whitelisted_tokens.to_vec()
+    }
+
+    /// Returns list of tokens whitelisted by given user.
+    pub fn get_user_whitelisted_tokens(&self, account_id: ValidAccountId) -> Vec<AccountId> {
+        self.internal_get_account(account_id.as_ref())
+            .map(|account| account.whitelisted_tokens.to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether new pools can only be created from whitelisted tokens.
+    pub fn get_whitelisted_pool_tokens_only(&self) -> bool {
+        self.whitelisted_pool_tokens_only
+    }
+
+    /// Returns deposits of given user of given length from given start index, in registration order of tokens.
+    pub fn get_deposits_paged(
+        &self,
+        account_id: ValidAccountId,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<(AccountId, U128)> {
+        if let Some(account) = self.internal_get_account(account_id.as_ref()) {
+            let keys = account.tokens.keys_as_vector();
+            let values = account.tokens.values_as_vector();
+            (from_index..std::cmp::min(from_index + limit, keys.len()))
+                .map(|index| (keys.get(index).unwrap(), U128(values.get(index).unwrap())))
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Returns number of registered accounts.
+    /// Accounts of the first deployment are counted once saved again or backfilled by the owner.
+    pub fn get_user_count(&self) -> u64 {
+        self.account_ids.len()
+    }
+
+    /// Returns list of registered accounts of given length from given start index.
+    pub fn list_accounts(&self, from_index: u64, limit: u64) -> Vec<AccountId> {
+        let account_ids = self.account_ids.as_vector();
+        (from_index..std::cmp::min(from_index + limit, account_ids.len()))
+            .map(|index| account_ids.get(index).unwrap())
+            .collect()
+    }
+
+    /// Returns ids of pools where given account holds shares, of given length from given start index.
+    pub fn get_user_pools(&self, account_id: ValidAccountId, from_index: u64, limit: u64) -> Vec<u64> {
+        self.internal_get_account_pools(account_id.as_ref())
+            .into_iter()
+            .filter(|pool_id| {
+                self.internal_get_pool(*pool_id)
+                    .unwrap()
+                    .share_balances(account_id.as_ref())
+                    > 0
+            })
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Returns balance of the deposit of given token for given user,
+    /// 0 if the user or the token is not registered.
+    pub fn get_token_balance(&self, account_id: ValidAccountId, token_id: ValidAccountId) -> U128 {
+        self.internal_get_deposit(account_id.as_ref(), token_id.as_ref())
+            .into()
+    }
+
+    /// Returns deposits of given user together with its shares in pools,
+    /// and the amounts of tokens these shares are worth.
+    pub fn get_user_portfolio(&self, account_id: ValidAccountId) -> UserPortfolio {
+        let pools = self
+            .internal_get_account_pools(account_id.as_ref())
+            .into_iter()
+            .filter_map(|pool_id| {
+                let pool = self.internal_get_pool(pool_id).unwrap();
+                let shares = pool.share_balances(account_id.as_ref());
+                if shares == 0 {
+                    return None;
+                }
+                let total_shares = pool.share_total_balance();
+                Some(UserPoolShares {
+                    pool_id,
+                    shares: U128(shares),
+                    token_account_ids: pool.tokens().to_vec(),
+                    amounts: pool
+                        .get_amounts()
+                        .into_iter()
+                        .map(|amount| {
+                            U128((U256::from(amount) * U256::from(shares)
+                                / U256::from(total_shares))
+                            .as_u128())
+                        })
+                        .collect(),
+                })
+            })
+            .collect();
+        UserPortfolio {
+            deposits: self.get_deposits(account_id),
+            pools,
+        }
     }
 }
\ No newline at end of file
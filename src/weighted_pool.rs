@@ -0,0 +1,535 @@
+use std::cmp::min;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, AccountId, Balance};
+
+use crate::admin_fee::AdminFees;
+use crate::errors::*;
+use crate::simple_pool::INIT_SHARES_SUPPLY;
+use crate::utils::{add_to_collection, SwapVolume, FEE_DIVISOR, MIN_LIQUIDITY, PRICE_PRECISION, U256};
+use crate::StorageKey;
+
+/// Weights of all tokens in the pool sum up to this value.
+pub const WEIGHT_DIVISOR: u32 = 10_000;
+/// Minimum weight of a single token (1%).
+pub const MIN_WEIGHT: u32 = 100;
+/// Minimum number of tokens in the pool.
+pub const MIN_NUM_TOKENS: usize = 2;
+/// Maximum number of tokens in the pool.
+pub const MAX_NUM_TOKENS: usize = 8;
+
+/// Fixed point one used by the weighted math.
+const BONE: u128 = 1_000_000_000_000_000_000;
+/// Precision of the fractional power approximation.
+const BPOW_PRECISION: u128 = BONE / 10_000_000_000;
+/// Maximum amount in of a swap relative to the pool's balance of the input token.
+const MAX_IN_RATIO: u128 = BONE / 2;
+/// Maximum amount out of a swap relative to the pool's balance of the output token.
+const MAX_OUT_RATIO: u128 = BONE / 3 + 1;
+
+
+/// Balancer-style pool, where value of each token is fixed share (weight) of the pool.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct WeightedPool {
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// Weight of each token, sum of weights equals WEIGHT_DIVISOR.
+    pub weights: Vec<u32>,
+    /// How much of each token this pool has.
+    pub amounts: Vec<Balance>,
+    /// Volumes accumulated by this pool.
+    pub volumes: Vec<SwapVolume>,
+    /// Fees accumulated by this pool, in the input token of each swap.
+    pub fees: Vec<Balance>,
+    /// Fee charged for swap (gets divided by FEE_DIVISOR).
+    pub total_fee: u32,
+    /// Shares of the pool by liquidity providers.
+    pub shares: LookupMap<AccountId, Balance>,
+    /// Total number of shares.
+    pub shares_total_supply: Balance,
+    /// Shares locked forever by the first deposit, pools created before the lock have none.
+    pub locked_shares: Balance,
+}
+
+impl WeightedPool {
+    pub fn new(
+        id: u32,
+        token_account_ids: Vec<ValidAccountId>,
+        weights: Vec<u32>,
+        total_fee: u32,
+    ) -> Self {
+        assert!(total_fee < FEE_DIVISOR, "{}", ERR50_FEE_TOO_LARGE);
+        assert!(
+            (MIN_NUM_TOKENS..=MAX_NUM_TOKENS).contains(&token_account_ids.len()),
+            "{}", ERR30_WRONG_TOKEN_COUNT
+        );
+        assert_eq!(
+            weights.len(),
+            token_account_ids.len(),
+            "{}", ERR60_WRONG_WEIGHTS_COUNT
+        );
+        for weight in weights.iter() {
+            assert!(*weight >= MIN_WEIGHT, "{}", ERR62_WEIGHT_TOO_SMALL);
+        }
+        assert_eq!(
+            weights.iter().sum::<u32>(),
+            WEIGHT_DIVISOR,
+            "{}", ERR61_WRONG_WEIGHTS_SUM
+        );
+        Self {
+            token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
+            weights,
+            amounts: vec![0u128; token_account_ids.len()],
+            volumes: vec![SwapVolume::default(); token_account_ids.len()],
+            fees: vec![0u128; token_account_ids.len()],
+            total_fee,
+            shares: LookupMap::new(StorageKey::Shares { pool_id: id }),
+            shares_total_supply: 0,
+            locked_shares: 0,
+        }
+    }
+
+    /// Register given account with 0 balance in shares.
+    /// Storage payment should be checked by caller.
+    pub fn share_register(&mut self, account_id: &AccountId) {
+        if self.shares.contains_key(account_id) {
+            env::panic(ERR14_LP_ALREADY_REGISTERED.as_bytes());
+        }
+        self.shares.insert(account_id, &0);
+    }
+
+    /// Returns true if given account has a shares entry, even with zero balance.
+    pub fn share_is_registered(&self, account_id: &AccountId) -> bool {
+        self.shares.contains_key(account_id)
+    }
+
+    /// Removes shares entry of given account, its shares should be transferred out before.
+    /// Returns whether the account was registered.
+    pub fn share_unregister(&mut self, account_id: &AccountId) -> bool {
+        self.shares.remove(account_id).is_some()
+    }
+
+    /// Transfer shares from predecessor to receiver.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        let balance = self.shares.get(sender_id).expect(ERR33_NO_SHARES);
+        if let Some(new_balance) = balance.checked_sub(amount) {
+            self.shares.insert(sender_id, &new_balance);
+        } else {
+            env::panic(ERR34_NOT_ENOUGH_SHARES.as_bytes())
+        }
+        let balance_out = self
+            .shares
+            .get(receiver_id)
+            .expect(ERR13_LP_NOT_REGISTERED);
+        self.shares.insert(receiver_id, &(balance_out + amount));
+    }
+
+    /// Returns balance of shares for given user.
+    pub fn share_balance_of(&self, account_id: &AccountId) -> Balance {
+        self.shares.get(account_id).unwrap_or_default()
+    }
+
+    /// Returns total number of shares in this pool.
+    pub fn share_total_balance(&self) -> Balance {
+        self.shares_total_supply
+    }
+
+    /// Returns list of tokens in this pool.
+    pub fn tokens(&self) -> &[AccountId] {
+        &self.token_account_ids
+    }
+
+    /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
+    /// After the first deposit, which sets the prices, amounts are taken in the pool proportion.
+    #[allow(clippy::needless_range_loop)]
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut [Balance]) -> Balance {
+        assert_eq!(
+            amounts.len(),
+            self.token_account_ids.len(),
+            "{}", ERR30_WRONG_TOKEN_COUNT
+        );
+        let shares = if self.shares_total_supply > 0 {
+            let mut fair_supply = U256::max_value();
+            for i in 0..self.token_account_ids.len() {
+                assert!(amounts[i] > 0, "{}", ERR31_ZERO_AMOUNT);
+                fair_supply = min(
+                    fair_supply,
+                    U256::from(amounts[i]) * U256::from(self.shares_total_supply) / self.amounts[i],
+                );
+            }
+            for i in 0..self.token_account_ids.len() {
+                let amount = (U256::from(self.amounts[i]) * fair_supply
+                    / U256::from(self.shares_total_supply))
+                .as_u128();
+                assert!(amount > 0, "{}", ERR31_ZERO_AMOUNT);
+                self.amounts[i] += amount;
+                amounts[i] = amount;
+            }
+            fair_supply.as_u128()
+        } else {
+            for i in 0..self.token_account_ids.len() {
+                assert!(amounts[i] > 0, "{}", ERR31_ZERO_AMOUNT);
+                self.amounts[i] += amounts[i];
+            }
+            self.mint_shares(&env::current_account_id(), MIN_LIQUIDITY);
+            self.locked_shares = MIN_LIQUIDITY;
+            INIT_SHARES_SUPPLY - MIN_LIQUIDITY
+        };
+        assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
+        self.mint_shares(sender_id, shares);
+        shares
+    }
+
+    /// Mint new shares for given user.
+    fn mint_shares(&mut self, account_id: &AccountId, shares: Balance) {
+        if shares == 0 {
+            return;
+        }
+        self.shares_total_supply += shares;
+        add_to_collection(&mut self.shares, account_id, shares);
+    }
+
+    /// Removes given number of shares form the pool and returns amounts to the parent.
+    #[allow(clippy::needless_range_loop)]
+    pub fn remove_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        min_amounts: Vec<Balance>,
+    ) -> Vec<Balance> {
+        assert_eq!(
+            min_amounts.len(),
+            self.token_account_ids.len(),
+            "{}", ERR30_WRONG_TOKEN_COUNT
+        );
+        let prev_shares_amount = self.shares.get(sender_id).expect(ERR33_NO_SHARES);
+        assert!(prev_shares_amount >= shares, "{}", ERR34_NOT_ENOUGH_SHARES);
+        assert!(
+            self.shares_total_supply - shares >= self.locked_shares,
+            "{}", ERR37_MIN_LIQUIDITY
+        );
+        let mut result = vec![];
+        for i in 0..self.token_account_ids.len() {
+            let amount = (U256::from(self.amounts[i]) * U256::from(shares)
+                / U256::from(self.shares_total_supply))
+            .as_u128();
+            assert!(amount >= min_amounts[i], "{}", ERR35_MIN_AMOUNT);
+            self.amounts[i] -= amount;
+            result.push(amount);
+        }
+        self.shares.insert(sender_id, &(prev_shares_amount - shares));
+        self.shares_total_supply -= shares;
+        result
+    }
+
+    /// Returns token index for given pool.
+    fn token_index(&self, token_id: &AccountId) -> usize {
+        self.token_account_ids
+            .iter()
+            .position(|id| id == token_id)
+            .expect(ERR41_MISSING_TOKEN)
+    }
+
+    /// Weight of given token as fixed point fraction of one.
+    fn normalized_weight(&self, token_idx: usize) -> U256 {
+        U256::from(self.weights[token_idx]) * U256::from(BONE) / U256::from(WEIGHT_DIVISOR)
+    }
+
+    /// Returns number of tokens in outcome, given amount.
+    /// out = balance_out * (1 - (balance_in / (balance_in + amount_in_after_fee)) ^ (weight_in / weight_out))
+    fn internal_get_return(&self, token_in: usize, amount_in: Balance, token_out: usize) -> Balance {
+        let in_balance = U256::from(self.amounts[token_in]);
+        let out_balance = U256::from(self.amounts[token_out]);
+        assert!(
+            in_balance > U256::zero()
+                && out_balance > U256::zero()
+                && token_in != token_out
+                && amount_in > 0,
+            "{}", ERR48_INVALID_SWAP
+        );
+        // The power approximation loses precision far from 1, so swaps are bounded as in Balancer.
+        assert!(
+            U256::from(amount_in) <= bmul(in_balance, U256::from(MAX_IN_RATIO)),
+            "{}", ERR49_MAX_IN_RATIO
+        );
+        let amount_with_fee =
+            U256::from(amount_in) * U256::from(FEE_DIVISOR - self.total_fee) / U256::from(FEE_DIVISOR);
+        let weight_ratio = bdiv(self.normalized_weight(token_in), self.normalized_weight(token_out));
+        let base = bdiv(in_balance, in_balance + amount_with_fee);
+        // Round the power up, so approximation error never goes against the pool.
+        let power = min(bpow(base, weight_ratio) + U256::from(BPOW_PRECISION), U256::from(BONE));
+        let amount_out = out_balance * (U256::from(BONE) - power) / U256::from(BONE);
+        assert!(
+            amount_out <= bmul(out_balance, U256::from(MAX_OUT_RATIO)),
+            "{}", ERR69_MAX_OUT_RATIO
+        );
+        amount_out.as_u128()
+    }
+
+    /// Returns how much token you will receive if swap `token_amount_in` of `token_in` for `token_out`.
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.internal_get_return(
+            self.token_index(token_in),
+            amount_in,
+            self.token_index(token_out),
+        )
+    }
+
+    /// Returns how much `token_out` one unit of `token_in` is worth at current reserves, excluding fees.
+    /// Multiplied by PRICE_PRECISION.
+    pub fn get_spot_price(&self, token_in: &AccountId, token_out: &AccountId) -> Balance {
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        assert!(
+            self.amounts[in_idx] > 0 && self.amounts[out_idx] > 0 && in_idx != out_idx,
+            "{}", ERR48_INVALID_SWAP
+        );
+        // (out_balance / out_weight) / (in_balance / in_weight)
+        (U256::from(self.amounts[out_idx])
+            * U256::from(self.weights[in_idx])
+            * U256::from(PRICE_PRECISION)
+            / (U256::from(self.amounts[in_idx]) * U256::from(self.weights[out_idx])))
+        .as_u128()
+    }
+
+    /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
+    /// Assuming that `token_amount_in` was already received from `sender_id`.
+    /// Exchange and referral parts of the fee are minted to them as LP shares.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let amount_out = self.internal_get_return(in_idx, amount_in, out_idx);
+        assert!(amount_out >= min_amount_out, "{}", ERR35_MIN_AMOUNT);
+
+        self.amounts[in_idx] += amount_in;
+        self.amounts[out_idx] -= amount_out;
+
+        // Exchange and referral parts of the fee are already in the pool, convert them into shares.
+        let fee = (U256::from(amount_in) * U256::from(self.total_fee) / U256::from(FEE_DIVISOR)).as_u128();
+        let exchange_fee =
+            (U256::from(fee) * U256::from(admin_fee.exchange_fee) / U256::from(FEE_DIVISOR)).as_u128();
+        let referral_fee = match &admin_fee.referral_id {
+            Some(referral_id) if self.shares.contains_key(referral_id) => {
+                (U256::from(fee) * U256::from(admin_fee.referral_fee) / U256::from(FEE_DIVISOR))
+                    .as_u128()
+            }
+            _ => 0,
+        };
+        let exchange_shares = self.fee_to_shares(in_idx, exchange_fee + referral_fee, exchange_fee);
+        let referral_shares = self.fee_to_shares(in_idx, exchange_fee + referral_fee, referral_fee);
+        self.mint_shares(&admin_fee.exchange_id, exchange_shares);
+        if let Some(referral_id) = &admin_fee.referral_id {
+            self.mint_shares(referral_id, referral_shares);
+        }
+
+        // Keeping track of volume per each input traded separately.
+        self.volumes[in_idx].input.0 += amount_in;
+        self.volumes[out_idx].output.0 += amount_out;
+        self.fees[in_idx] += fee;
+
+        amount_out
+    }
+
+    /// Returns number of shares worth `part` out of `total` amount of given token,
+    /// which is already in the pool, as if `total` was deposited as single token.
+    /// shares = supply * ((balance / (balance - total)) ^ weight - 1) * part / total
+    fn fee_to_shares(&self, token_idx: usize, total: Balance, part: Balance) -> Balance {
+        if part == 0 || self.shares_total_supply == 0 {
+            return 0;
+        }
+        let balance = U256::from(self.amounts[token_idx]);
+        let base = bdiv(balance, balance - U256::from(total));
+        let power = bpow(base, self.normalized_weight(token_idx));
+        // Round the power down, so approximation error never goes against the LPs.
+        let growth = power.saturating_sub(U256::from(BONE) + U256::from(BPOW_PRECISION));
+        (U256::from(self.shares_total_supply) * growth / U256::from(BONE) * U256::from(part)
+            / U256::from(total))
+        .as_u128()
+    }
+
+    /// Returns given pool's total fee.
+    pub fn get_fee(&self) -> u32 {
+        self.total_fee
+    }
+
+    /// Returns volumes of the given pool.
+    pub fn get_volumes(&self) -> Vec<SwapVolume> {
+        self.volumes.clone()
+    }
+
+    /// Returns fees accumulated by the given pool.
+    pub fn get_fees(&self) -> Vec<Balance> {
+        self.fees.clone()
+    }
+}
+
+/// Fixed point multiplication, rounded to nearest.
+fn bmul(a: U256, b: U256) -> U256 {
+    (a * b + U256::from(BONE / 2)) / U256::from(BONE)
+}
+
+/// Fixed point division, rounded to nearest.
+fn bdiv(a: U256, b: U256) -> U256 {
+    (a * U256::from(BONE) + b / 2) / b
+}
+
+/// Fixed point power with integer exponent.
+fn bpowi(base: U256, exp: u128) -> U256 {
+    let mut result = if exp % 2 == 1 { base } else { U256::from(BONE) };
+    let mut base = base;
+    let mut exp = exp / 2;
+    while exp != 0 {
+        base = bmul(base, base);
+        if exp % 2 == 1 {
+            result = bmul(result, base);
+        }
+        exp /= 2;
+    }
+    result
+}
+
+/// Fixed point power with fractional exponent.
+/// Base must be in (0, 2), which holds for all ratios of balances used by the pool.
+fn bpow(base: U256, exp: U256) -> U256 {
+    assert!(
+        base > U256::zero() && base < U256::from(2 * BONE),
+        "{}", ERR63_BPOW_BASE
+    );
+    let whole = exp / U256::from(BONE);
+    let remain = exp % U256::from(BONE);
+    let whole_pow = bpowi(base, whole.as_u128());
+    if remain.is_zero() {
+        return whole_pow;
+    }
+    bmul(whole_pow, bpow_approx(base, remain))
+}
+
+/// Approximates base ^ exp for fractional exp with binomial series of (1 + x) ^ exp.
+fn bpow_approx(base: U256, exp: U256) -> U256 {
+    let one = U256::from(BONE);
+    let (x, x_neg) = sub_sign(base, one);
+    let mut term = one;
+    let mut sum = one;
+    let mut negative = false;
+    let mut i = 1u128;
+    while term >= U256::from(BPOW_PRECISION) {
+        let big_k = U256::from(i) * one;
+        let (c, c_neg) = sub_sign(exp, big_k - one);
+        term = bdiv(bmul(term, bmul(c, x)), big_k);
+        if term.is_zero() {
+            break;
+        }
+        if x_neg {
+            negative = !negative;
+        }
+        if c_neg {
+            negative = !negative;
+        }
+        if negative {
+            sum = sum.saturating_sub(term);
+        } else {
+            sum += term;
+        }
+        i += 1;
+    }
+    sum
+}
+
+/// Returns |a - b| and whether the difference is negative.
+fn sub_sign(a: U256, b: U256) -> (U256, bool) {
+    if a >= b {
+        (a - b, false)
+    } else {
+        (b - a, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    /// Converts decimal fixed point given as integer and fractional digits into BONE units.
+    fn bnum(whole: u128, fraction: u128, fraction_digits: u32) -> U256 {
+        U256::from(whole * BONE + fraction * 10u128.pow(18 - fraction_digits))
+    }
+
+    fn assert_close(actual: U256, expected: U256) {
+        let diff = if actual > expected { actual - expected } else { expected - actual };
+        assert!(diff <= U256::from(BPOW_PRECISION), "{} != {}", actual, expected);
+    }
+
+    fn setup_pool(weights: Vec<u32>, amounts: Vec<Balance>) -> WeightedPool {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.current_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(0, vec![accounts(1), accounts(2)], weights, 30);
+        let mut amounts = amounts;
+        pool.add_liquidity(&accounts(3).into(), &mut amounts);
+        pool
+    }
+
+    fn no_admin_fee() -> AdminFees {
+        AdminFees::new(0, 0, accounts(0).into(), None)
+    }
+
+    #[test]
+    fn test_bpow() {
+        assert_eq!(bpow(bnum(0, 5, 1), bnum(2, 0, 0)), bnum(0, 25, 2));
+        assert_eq!(bpow(bnum(1, 5, 1), bnum(3, 0, 0)), bnum(3, 375, 3));
+        assert_close(bpow(bnum(0, 5, 1), bnum(0, 5, 1)), bnum(0, 707106781186547524, 18));
+        assert_close(bpow(bnum(0, 9, 1), bnum(1, 5, 1)), bnum(0, 853814968245462419, 18));
+        assert_close(bpow(bnum(1, 2, 1), bnum(2, 3, 1)), bnum(1, 520956754552531765, 18));
+    }
+
+    #[test]
+    fn test_bpow_approx() {
+        assert_eq!(bpow_approx(bnum(1, 0, 0), bnum(0, 5, 1)), bnum(1, 0, 0));
+        assert_close(bpow_approx(bnum(1, 5, 1), bnum(0, 5, 1)), bnum(1, 224744871391589049, 18));
+        assert_close(bpow_approx(bnum(0, 5, 1), bnum(0, 5, 1)), bnum(0, 707106781186547524, 18));
+    }
+
+    #[test]
+    #[should_panic(expected = "E63: power base out of range")]
+    fn test_bpow_base_out_of_range() {
+        bpow(bnum(2, 0, 0), bnum(0, 5, 1));
+    }
+
+    #[test]
+    fn test_swap_within_ratios() {
+        let mut pool = setup_pool(vec![5000, 5000], vec![100_000_000, 100_000_000]);
+        // Equal weights behave as constant product: 50M * 0.997 * 100M / (100M + 50M * 0.997).
+        let amount_out =
+            pool.swap(&accounts(1).into(), 50_000_000, &accounts(2).into(), 0, &no_admin_fee());
+        assert_eq!(amount_out, 33_266_599);
+    }
+
+    #[test]
+    #[should_panic(expected = "E49: amount in exceeds max ratio of pool balance")]
+    fn test_swap_max_in_ratio() {
+        let pool = setup_pool(vec![5000, 5000], vec![100_000_000, 100_000_000]);
+        pool.get_return(&accounts(1).into(), 50_000_001, &accounts(2).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "E69: amount out exceeds max ratio of pool balance")]
+    fn test_swap_max_out_ratio() {
+        let mut pool = setup_pool(vec![8000, 2000], vec![100_000_000, 100_000_000]);
+        pool.swap(&accounts(1).into(), 40_000_000, &accounts(2).into(), 0, &no_admin_fee());
+    }
+}